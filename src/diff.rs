@@ -0,0 +1,93 @@
+use crate::value::Value;
+
+/// A difference between two values, at a dotted path in the style of
+/// [`Value::query`]. The path of the root is empty.
+#[derive(Debug, Clone)]
+pub enum Change<'source> {
+	/// The path only exists in the new value.
+	Added { path: String, value: Value<'source> },
+	/// The path only exists in the old value.
+	Removed { path: String, value: Value<'source> },
+	/// The path holds a different value in each.
+	Modified {
+		path: String,
+		old: Value<'source>,
+		new: Value<'source>,
+	},
+}
+
+impl<'source> Value<'source> {
+	/// Lists what changed going from `self` to `other`.
+	///
+	/// Attrsets are compared attribute by attribute, in sorted order, and
+	/// lists element by element; anything else that isn't equal is reported
+	/// as modified as a whole.
+	pub fn diff(&self, other: &Value<'source>) -> Vec<Change<'source>> {
+		let mut changes = Vec::new();
+		diff_at(&mut changes, String::new(), self, other);
+		changes
+	}
+}
+
+fn diff_at<'source>(
+	changes: &mut Vec<Change<'source>>,
+	path: String,
+	old: &Value<'source>,
+	new: &Value<'source>,
+) {
+	match (old, new) {
+		(Value::AttrSet(a), Value::AttrSet(b))
+		| (Value::RecAttrSet(a), Value::RecAttrSet(b)) => {
+			let mut keys = a.keys().chain(b.keys()).collect::<Vec<_>>();
+			keys.sort();
+			keys.dedup();
+
+			for key in keys {
+				let path = join(&path, key);
+				match (a.get(key), b.get(key)) {
+					(Some(old), Some(new)) => diff_at(changes, path, old, new),
+					(Some(old), None) => changes.push(Change::Removed {
+						path,
+						value: old.clone(),
+					}),
+					(None, Some(new)) => changes.push(Change::Added {
+						path,
+						value: new.clone(),
+					}),
+					(None, None) => unreachable!("key came from either map"),
+				}
+			}
+		}
+		(Value::List(a), Value::List(b)) => {
+			for i in 0..a.len().max(b.len()) {
+				let path = join(&path, &i.to_string());
+				match (a.get(i), b.get(i)) {
+					(Some(old), Some(new)) => diff_at(changes, path, old, new),
+					(Some(old), None) => changes.push(Change::Removed {
+						path,
+						value: old.clone(),
+					}),
+					(None, Some(new)) => changes.push(Change::Added {
+						path,
+						value: new.clone(),
+					}),
+					(None, None) => unreachable!("index is within either list"),
+				}
+			}
+		}
+		_ if old.approx_eq(new, 0.0) => (),
+		_ => changes.push(Change::Modified {
+			path,
+			old: old.clone(),
+			new: new.clone(),
+		}),
+	}
+}
+
+fn join(path: &str, segment: &str) -> String {
+	if path.is_empty() {
+		segment.to_owned()
+	} else {
+		format!("{path}.{segment}")
+	}
+}
@@ -0,0 +1,618 @@
+use crate::{
+	lexer::Token,
+	value::{Map, Pattern, Value},
+};
+use logos::Logos;
+use miette::Diagnostic;
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic, PartialEq)]
+pub enum EvalError {
+	#[error("integer overflow in {0}")]
+	#[diagnostic(code(nixc::eval::overflow), url(docsrs))]
+	Overflow(&'static str),
+
+	#[error("division by zero")]
+	#[diagnostic(code(nixc::eval::division_by_zero), url(docsrs))]
+	DivisionByZero,
+
+	#[error("cannot apply {op} to {lhs} and {rhs}")]
+	#[diagnostic(code(nixc::eval::type_mismatch), url(docsrs))]
+	TypeMismatch {
+		op: &'static str,
+		lhs: &'static str,
+		rhs: &'static str,
+	},
+
+	#[error("cannot negate {0}")]
+	#[diagnostic(code(nixc::eval::not_numeric), url(docsrs))]
+	NotNumeric(&'static str),
+
+	#[error("the condition of `if` must be a bool, got {0}")]
+	#[diagnostic(code(nixc::eval::not_a_bool), url(docsrs))]
+	NotABool(&'static str),
+
+	#[error("undefined variable `{0}`")]
+	#[diagnostic(code(nixc::eval::undefined_variable), url(docsrs))]
+	UndefinedVariable(String),
+
+	#[error("infinite recursion while resolving `{0}`")]
+	#[diagnostic(code(nixc::eval::infinite_recursion), url(docsrs))]
+	InfiniteRecursion(String),
+
+	#[error("attribute `{0}` is missing")]
+	#[diagnostic(code(nixc::eval::missing_attribute), url(docsrs))]
+	MissingAttribute(String),
+
+	#[error("`with` needs a set, got {0}")]
+	#[diagnostic(code(nixc::eval::with_not_a_set), url(docsrs))]
+	WithNotASet(&'static str),
+
+	#[error("function calls nested more than {0} deep")]
+	#[diagnostic(
+		code(nixc::eval::call_depth),
+		url(docsrs),
+		help("this is most likely infinite recursion through a function")
+	)]
+	CallDepth(usize),
+
+	#[error("attempt to call {0}, which is not a function")]
+	#[diagnostic(code(nixc::eval::not_a_function), url(docsrs))]
+	NotAFunction(&'static str),
+
+	#[error("cannot coerce {0} to a string in an interpolation")]
+	#[diagnostic(code(nixc::eval::not_coercible), url(docsrs))]
+	NotCoercible(&'static str),
+
+	#[error("function called with {0} where it takes an attrset")]
+	#[diagnostic(code(nixc::eval::argument_not_a_set), url(docsrs))]
+	ArgumentNotASet(&'static str),
+
+	#[error("function called without required argument `{0}`")]
+	#[diagnostic(code(nixc::eval::missing_argument), url(docsrs))]
+	MissingArgument(String),
+
+	#[error("function called with unexpected argument `{0}`")]
+	#[diagnostic(code(nixc::eval::unexpected_argument), url(docsrs))]
+	UnexpectedArgument(String),
+}
+
+/// A binary operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+	Add,
+	Sub,
+	Mul,
+	Div,
+	Concat,
+	Eq,
+	/// `a // b`, the attributes of `a` updated with those of `b`.
+	Update,
+}
+
+impl BinOp {
+	/// Binding power of the operator; higher binds tighter.
+	pub fn precedence(self) -> u8 {
+		match self {
+			BinOp::Eq => 0,
+			BinOp::Update => 1,
+			BinOp::Add | BinOp::Sub => 2,
+			BinOp::Mul | BinOp::Div => 3,
+			BinOp::Concat => 4,
+		}
+	}
+
+	/// Whether `a op b op c` groups as `a op (b op c)`.
+	pub fn is_right_associative(self) -> bool {
+		matches!(self, BinOp::Concat)
+	}
+
+	/// The name of the operation, as used in diagnostics.
+	pub fn name(self) -> &'static str {
+		match self {
+			BinOp::Add => "addition",
+			BinOp::Sub => "subtraction",
+			BinOp::Mul => "multiplication",
+			BinOp::Div => "division",
+			BinOp::Concat => "concatenation",
+			BinOp::Eq => "equality",
+			BinOp::Update => "update",
+		}
+	}
+}
+
+/// Applies `op` to two operands if both are already values, or keeps the
+/// operation around to be evaluated later otherwise. Raw numbers are kept
+/// as written, so operations on them aren't folded either.
+///
+/// Equality is only folded between scalars, since a list or attrset might
+/// hold something unevaluated, and an update isn't folded into a `rec`
+/// attrset, whose bindings could refer to the attributes it replaces.
+pub fn fold<'source>(
+	op: BinOp,
+	lhs: Value<'source>,
+	rhs: Value<'source>,
+) -> Result<Value<'source>, EvalError> {
+	let foldable = match op {
+		BinOp::Eq => lhs.is_scalar() && rhs.is_scalar(),
+		BinOp::Update => {
+			!matches!(lhs, Value::RecAttrSet(_))
+				&& !matches!(rhs, Value::RecAttrSet(_))
+		}
+		_ => true,
+	};
+	if is_unevaluated(&lhs) || is_unevaluated(&rhs) || !foldable {
+		Ok(Value::BinOp(op, Box::new(lhs), Box::new(rhs)))
+	} else {
+		binary(op, &lhs, &rhs)
+	}
+}
+
+/// Applies unary minus to an operand, which, like in Nix, is subtraction
+/// from zero when the operand isn't a value yet.
+pub fn fold_negate(value: Value) -> Result<Value, EvalError> {
+	if is_unevaluated(&value) {
+		Ok(Value::BinOp(
+			BinOp::Sub,
+			Box::new(Value::Integer(0)),
+			Box::new(value),
+		))
+	} else {
+		negate(&value)
+	}
+}
+
+/// Picks the branch of an `if` if its condition is already a value, or
+/// keeps the whole expression around to be evaluated later otherwise.
+pub fn fold_if<'source>(
+	cond: Value<'source>,
+	then: Value<'source>,
+	otherwise: Value<'source>,
+) -> Result<Value<'source>, EvalError> {
+	match cond {
+		Value::Bool(true) => Ok(then),
+		Value::Bool(false) => Ok(otherwise),
+		_ if is_unevaluated(&cond) => Ok(Value::If(
+			Box::new(cond),
+			Box::new(then),
+			Box::new(otherwise),
+		)),
+		_ => Err(EvalError::NotABool(cond.type_name())),
+	}
+}
+
+/// Applies `op` to two evaluated operands.
+///
+/// Integer arithmetic is checked, so overflow is reported instead of
+/// wrapping. Mixing an integer with a float promotes the integer, like Nix.
+/// Any two values can be compared for equality, which is structural.
+pub fn binary<'source>(
+	op: BinOp,
+	lhs: &Value<'source>,
+	rhs: &Value<'source>,
+) -> Result<Value<'source>, EvalError> {
+	if op == BinOp::Eq {
+		return Ok(Value::Bool(lhs.approx_eq(rhs, 0.0)));
+	}
+
+	let mismatch = || EvalError::TypeMismatch {
+		op: op.name(),
+		lhs: lhs.type_name(),
+		rhs: rhs.type_name(),
+	};
+
+	match (lhs, rhs) {
+		(Value::List(a), Value::List(b)) if op == BinOp::Concat => {
+			Ok(Value::List(a.iter().chain(b).cloned().collect()))
+		}
+		// Unlike a deep merge, nested attrsets are replaced whole.
+		(Value::AttrSet(a), Value::AttrSet(b)) if op == BinOp::Update => {
+			let mut updated = a.clone();
+			updated.extend(b.iter().map(|(k, v)| (k.clone(), v.clone())));
+			Ok(Value::AttrSet(updated))
+		}
+		(Value::Integer(a), Value::Integer(b)) => {
+			let (a, b) = (*a, *b);
+			let result = match op {
+				BinOp::Add => a.checked_add(b),
+				BinOp::Sub => a.checked_sub(b),
+				BinOp::Mul => a.checked_mul(b),
+				BinOp::Div if b == 0 => return Err(EvalError::DivisionByZero),
+				BinOp::Div => a.checked_div(b),
+				BinOp::Concat | BinOp::Eq | BinOp::Update => {
+					return Err(mismatch());
+				}
+			};
+			result
+				.map(Value::Integer)
+				.ok_or(EvalError::Overflow(op.name()))
+		}
+		(
+			Value::Integer(_) | Value::Float(_),
+			Value::Integer(_) | Value::Float(_),
+		) => {
+			let (a, b) = (as_float(lhs), as_float(rhs));
+			match op {
+				BinOp::Add => Ok(Value::Float(a + b)),
+				BinOp::Sub => Ok(Value::Float(a - b)),
+				BinOp::Mul => Ok(Value::Float(a * b)),
+				BinOp::Div if b == 0.0 => Err(EvalError::DivisionByZero),
+				BinOp::Div => Ok(Value::Float(a / b)),
+				BinOp::Concat | BinOp::Eq | BinOp::Update => Err(mismatch()),
+			}
+		}
+		_ => Err(mismatch()),
+	}
+}
+
+/// Applies unary minus to an evaluated operand.
+pub fn negate<'source>(
+	value: &Value<'source>,
+) -> Result<Value<'source>, EvalError> {
+	match value {
+		Value::Integer(n) => n
+			.checked_neg()
+			.map(Value::Integer)
+			.ok_or(EvalError::Overflow("negation")),
+		Value::Float(n) => Ok(Value::Float(-n)),
+		_ => Err(EvalError::NotNumeric(value.type_name())),
+	}
+}
+
+fn is_unevaluated(value: &Value) -> bool {
+	matches!(
+		value,
+		Value::RawNumber(_)
+			| Value::LetIn(..)
+			| Value::Ref(_)
+			| Value::Inherit(_)
+			| Value::With(..)
+			| Value::If(..)
+			| Value::Select(..)
+			| Value::BinOp(..)
+			| Value::Apply(..)
+			| Value::Interpolate(_)
+	)
+}
+
+fn as_float(value: &Value) -> f64 {
+	match value {
+		Value::Integer(n) => *n as f64,
+		Value::Float(n) => *n,
+		_ => unreachable!("operand was checked to be numeric"),
+	}
+}
+
+impl<'source> Value<'source> {
+	/// Resolves `let`, `with`, references, selections and operations as far
+	/// as can be done without any outside input, leaving a plain data tree
+	/// that can be exported to JSON.
+	///
+	/// Like in Nix, the bindings of a `let` or `rec` attrset can refer to
+	/// each other, and those of a `with` never shadow them. Raw numbers are
+	/// replaced by their values. A function can't be resolved without an
+	/// argument, so it's kept inside the `let` and `with` scopes it was
+	/// defined in, which its body is evaluated in when it's called.
+	pub fn canonicalize(&self) -> Result<Value<'source>, EvalError> {
+		canonicalize_in(self, &[], &mut Resolving::default())
+	}
+}
+
+/// How deeply function calls may nest before evaluation gives up, since
+/// recursion through a call never ends up resolving the same binding twice
+/// in one scope, which is how other infinite recursion is caught.
+pub const MAX_CALL_DEPTH: usize = 100;
+
+/// What is being resolved, to catch evaluation that would never end.
+#[derive(Default)]
+struct Resolving {
+	/// The bindings being resolved, by the index of their scope.
+	bindings: Vec<(usize, String)>,
+	/// The number of function calls being evaluated.
+	calls: usize,
+}
+
+/// A scope in which references are resolved.
+#[derive(Clone, Copy)]
+enum Scope<'a, 'source> {
+	/// The unresolved bindings of a `let` or `rec` attrset.
+	Bindings(&'a Map<'source>),
+	/// The resolved attributes of a `with`.
+	With(&'a Map<'source>),
+}
+
+fn canonicalize_in<'source>(
+	value: &Value<'source>,
+	scopes: &[Scope<'_, 'source>],
+	resolving: &mut Resolving,
+) -> Result<Value<'source>, EvalError> {
+	match value {
+		Value::List(items) => Ok(Value::List(
+			items
+				.iter()
+				.map(|item| canonicalize_in(item, scopes, resolving))
+				.collect::<Result<_, _>>()?,
+		)),
+		Value::AttrSet(map) => Ok(Value::AttrSet(
+			map.iter()
+				.map(|(key, value)| {
+					Ok((
+						key.clone(),
+						canonicalize_in(value, scopes, resolving)?,
+					))
+				})
+				.collect::<Result<_, EvalError>>()?,
+		)),
+		Value::RecAttrSet(map) => {
+			let scopes = [scopes, &[Scope::Bindings(map)]].concat();
+			Ok(Value::AttrSet(
+				map.keys()
+					.map(|key| {
+						let value = resolve_binding(
+							&scopes,
+							scopes.len() - 1,
+							key,
+							resolving,
+						)?;
+						Ok((key.clone(), value))
+					})
+					.collect::<Result<_, EvalError>>()?,
+			))
+		}
+		Value::LetIn(bindings, body) => {
+			let scopes = [scopes, &[Scope::Bindings(bindings)]].concat();
+			canonicalize_in(body, &scopes, resolving)
+		}
+		Value::With(scope, body) => {
+			let scope = canonicalize_in(scope, scopes, resolving)?;
+			let Value::AttrSet(map) = &scope else {
+				return Err(EvalError::WithNotASet(scope.type_name()));
+			};
+			let scopes = [scopes, &[Scope::With(map)]].concat();
+			canonicalize_in(body, &scopes, resolving)
+		}
+		Value::If(cond, then, otherwise) => {
+			match canonicalize_in(cond, scopes, resolving)? {
+				Value::Bool(true) => canonicalize_in(then, scopes, resolving),
+				Value::Bool(false) => {
+					canonicalize_in(otherwise, scopes, resolving)
+				}
+				cond => Err(EvalError::NotABool(cond.type_name())),
+			}
+		}
+		Value::Ref(name) | Value::Inherit(name) => {
+			lookup(scopes, name, resolving)
+		}
+		Value::Select(value, path, default) => {
+			let mut current = canonicalize_in(value, scopes, resolving)?;
+			for name in path {
+				current = match current.get(name) {
+					Some(value) => value.clone(),
+					None => match default {
+						Some(default) => {
+							return canonicalize_in(default, scopes, resolving);
+						}
+						None => {
+							return Err(EvalError::MissingAttribute(
+								path.join("."),
+							));
+						}
+					},
+				};
+			}
+			Ok(current)
+		}
+		Value::BinOp(op, lhs, rhs) => binary(
+			*op,
+			&canonicalize_in(lhs, scopes, resolving)?,
+			&canonicalize_in(rhs, scopes, resolving)?,
+		),
+		Value::Null
+		| Value::Bool(_)
+		| Value::Integer(_)
+		| Value::Float(_)
+		| Value::String(_)
+		| Value::Path(_) => Ok(value.clone()),
+		Value::RawNumber(literal) => {
+			Ok(parse_number(literal).unwrap_or_else(|| value.clone()))
+		}
+		// The body can't be evaluated without an argument.
+		Value::Lambda(..) => Ok(close_over(value.clone(), scopes)),
+		Value::Apply(function, arg) => {
+			let function = canonicalize_in(function, scopes, resolving)?;
+			let arg = canonicalize_in(arg, scopes, resolving)?;
+			apply(&function, arg, resolving)
+		}
+		Value::Interpolate(parts) => {
+			let mut text = String::new();
+			for part in parts {
+				let part = canonicalize_in(part, scopes, resolving)?;
+				// Only strings, paths and sets with an `outPath` coerce;
+				// numbers need an explicit `toString`, as in Nix.
+				let coerced = part
+					.coerce_to_string()
+					.map_err(|_| EvalError::NotCoercible(part.type_name()))?;
+				text.push_str(&coerced);
+			}
+			Ok(Value::String(text.into()))
+		}
+	}
+}
+
+/// Binds the names of a function's `pattern` to the parts of `arg`. Defaults
+/// are bound unevaluated, so that, like in Nix, they can refer to the other
+/// parameters.
+fn bind_argument<'source>(
+	pattern: &Pattern<'source>,
+	arg: Value<'source>,
+) -> Result<Map<'source>, EvalError> {
+	let mut params = Map::new();
+	let (set_params, ellipsis, bind) = match pattern {
+		Pattern::Ident(name) => {
+			params.insert(name.clone(), arg);
+			return Ok(params);
+		}
+		Pattern::Set {
+			params,
+			ellipsis,
+			bind,
+		} => (params, *ellipsis, bind),
+	};
+
+	let Value::AttrSet(attrs) = &arg else {
+		return Err(EvalError::ArgumentNotASet(arg.type_name()));
+	};
+	if !ellipsis {
+		let mut names = attrs.keys().collect::<Vec<_>>();
+		names.sort();
+		if let Some(unexpected) = names
+			.into_iter()
+			.find(|name| !set_params.iter().any(|(param, _)| param == *name))
+		{
+			return Err(EvalError::UnexpectedArgument(unexpected.to_string()));
+		}
+	}
+	for (name, default) in set_params {
+		let value = match (attrs.get(name), default) {
+			(Some(value), _) => value.clone(),
+			(None, Some(default)) => default.clone(),
+			(None, None) => {
+				return Err(EvalError::MissingArgument(name.to_string()));
+			}
+		};
+		params.insert(name.clone(), value);
+	}
+	if let Some(bind) = bind {
+		params.insert(bind.clone(), arg);
+	}
+	Ok(params)
+}
+
+/// Keeps `scopes` around a function that was defined in them, outermost
+/// first, so that its body can still refer to them wherever it's called.
+fn close_over<'source>(
+	function: Value<'source>,
+	scopes: &[Scope<'_, 'source>],
+) -> Value<'source> {
+	scopes
+		.iter()
+		.rev()
+		.fold(function, |closure, scope| match scope {
+			Scope::Bindings(map) => {
+				Value::LetIn((*map).clone(), Box::new(closure))
+			}
+			Scope::With(map) => Value::With(
+				Box::new(Value::AttrSet((*map).clone())),
+				Box::new(closure),
+			),
+		})
+}
+
+/// Calls a resolved `function`, evaluating its body in the scopes it kept
+/// from where it was defined, and its parameters.
+fn apply<'source>(
+	function: &Value<'source>,
+	arg: Value<'source>,
+	resolving: &Resolving,
+) -> Result<Value<'source>, EvalError> {
+	let mut scopes = Vec::new();
+	let mut closure = function;
+	let (pattern, body) = loop {
+		match closure {
+			Value::LetIn(bindings, inner) => {
+				scopes.push(Scope::Bindings(bindings));
+				closure = inner;
+			}
+			Value::With(scope, inner) => {
+				let Value::AttrSet(map) = &**scope else {
+					return Err(EvalError::WithNotASet(scope.type_name()));
+				};
+				scopes.push(Scope::With(map));
+				closure = inner;
+			}
+			Value::Lambda(pattern, body) => break (pattern, body),
+			_ => return Err(EvalError::NotAFunction(function.type_name())),
+		}
+	};
+
+	if resolving.calls == MAX_CALL_DEPTH {
+		return Err(EvalError::CallDepth(MAX_CALL_DEPTH));
+	}
+	let params = bind_argument(pattern, arg)?;
+	scopes.push(Scope::Bindings(&params));
+	// Scopes are numbered by where they are in `scopes`, so the bindings
+	// being resolved outside the call don't carry over into it.
+	let mut resolving = Resolving {
+		bindings: Vec::new(),
+		calls: resolving.calls + 1,
+	};
+	canonicalize_in(body, &scopes, &mut resolving)
+}
+
+/// The value of a number literal kept as written, if it has one.
+fn parse_number<'source>(literal: &str) -> Option<Value<'source>> {
+	match Token::lexer(literal).next()? {
+		Ok(
+			Token::Integer(n)
+			| Token::HexInteger(n)
+			| Token::OctalInteger(n)
+			| Token::BinaryInteger(n),
+		) => Some(Value::Integer(n)),
+		Ok(Token::Float(n)) => Some(Value::Float(n)),
+		_ => None,
+	}
+}
+
+/// Resolves a reference, preferring the innermost binding of the name and
+/// only falling back to `with` scopes, innermost first, when there is none.
+/// So a `let` or function argument shadows every `with`, even one nested
+/// inside it, as in Nix.
+fn lookup<'source>(
+	scopes: &[Scope<'_, 'source>],
+	name: &str,
+	resolving: &mut Resolving,
+) -> Result<Value<'source>, EvalError> {
+	let bound = scopes.iter().rposition(
+		|scope| matches!(scope, Scope::Bindings(map) if map.contains_key(name)),
+	);
+	if let Some(index) = bound {
+		return resolve_binding(scopes, index, name, resolving);
+	}
+
+	scopes
+		.iter()
+		.rev()
+		.find_map(|scope| match scope {
+			Scope::With(map) => map.get(name).cloned(),
+			Scope::Bindings(_) => None,
+		})
+		.ok_or_else(|| EvalError::UndefinedVariable(name.to_owned()))
+}
+
+/// Resolves the binding of `name` in `scopes[index]`, in the scopes that
+/// were visible where it was bound.
+fn resolve_binding<'source>(
+	scopes: &[Scope<'_, 'source>],
+	index: usize,
+	name: &str,
+	resolving: &mut Resolving,
+) -> Result<Value<'source>, EvalError> {
+	let Scope::Bindings(map) = scopes[index] else {
+		unreachable!("only bindings are resolved lazily");
+	};
+	let key = (index, name.to_owned());
+	if resolving.bindings.contains(&key) {
+		return Err(EvalError::InfiniteRecursion(name.to_owned()));
+	}
+
+	resolving.bindings.push(key);
+	let result = match &map[name] {
+		// `inherit x` refers to the enclosing scope rather than to itself.
+		Value::Inherit(inherited) => {
+			lookup(&scopes[..index], inherited, resolving)
+		}
+		value => canonicalize_in(value, &scopes[..=index], resolving),
+	};
+	resolving.bindings.pop();
+	result
+}
@@ -0,0 +1,310 @@
+use crate::value::{Map, OwnedValue, Value};
+use logos::{Lexer, Logos};
+use miette::{Diagnostic, Result, SourceSpan};
+use std::borrow::Cow;
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum JsonError {
+	#[error("cannot export `{0}`, which refers to the enclosing scope, without a scope")]
+	#[diagnostic(code(nixc::json::unresolved_ref), url(docsrs))]
+	UnresolvedRef(String),
+
+	#[error("`{0}` is not defined in the given scope")]
+	#[diagnostic(code(nixc::json::undefined_ref), url(docsrs))]
+	UndefinedRef(String),
+
+	#[error("cannot export {0} to JSON")]
+	#[diagnostic(code(nixc::json::unsupported), url(docsrs))]
+	Unsupported(&'static str),
+
+	#[error("invalid JSON: {message}")]
+	#[diagnostic(code(nixc::json::invalid), url(docsrs))]
+	Invalid {
+		#[source_code]
+		src: String,
+
+		#[label("This right here")]
+		bad_bit: SourceSpan,
+
+		message: String,
+	},
+}
+
+#[derive(Logos, Debug, Clone, PartialEq)]
+#[logos(skip r"[ \t\r\n]+")]
+enum JsonToken<'a> {
+	#[token("false", |_| false)]
+	#[token("true", |_| true)]
+	Bool(bool),
+
+	#[token("{")]
+	BraceOpen,
+
+	#[token("}")]
+	BraceClose,
+
+	#[token("[")]
+	BracketOpen,
+
+	#[token("]")]
+	BracketClose,
+
+	#[token(":")]
+	Colon,
+
+	#[token(",")]
+	Comma,
+
+	#[token("null")]
+	Null,
+
+	#[regex(r"-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?")]
+	Number(&'a str),
+
+	#[regex(r#""([^"\\\x00-\x1F]|\\(["\\/bfnrt]|u[0-9a-fA-F]{4}))*""#)]
+	String(&'a str),
+}
+
+/// Loads a JSON document into the same kind of value parsing Nix produces,
+/// so either format can be used for configuration.
+///
+/// Integers that fit in an `i64` become integers; any other number becomes a
+/// float.
+pub fn from_json_str(json: &str) -> Result<OwnedValue> {
+	let mut lexer = JsonToken::lexer(json);
+	let value = parse_json_value(&mut lexer)?;
+
+	match lexer.next() {
+		None => Ok(value.into_owned()),
+		Some(_) => Err(invalid(&lexer, "unexpected token after the value"))?,
+	}
+}
+
+fn parse_json_value<'source>(
+	lexer: &mut Lexer<'source, JsonToken<'source>>,
+) -> Result<Value<'source>> {
+	match lexer.next() {
+		Some(Ok(JsonToken::Null)) => Ok(Value::Null),
+		Some(Ok(JsonToken::Bool(b))) => Ok(Value::Bool(b)),
+		Some(Ok(JsonToken::Number(n))) => Ok(match n.parse() {
+			Ok(n) => Value::Integer(n),
+			Err(_) => Value::Float(n.parse().expect("matched a JSON number")),
+		}),
+		Some(Ok(JsonToken::String(s))) => Ok(Value::String(decode_string(s)?)),
+		Some(Ok(JsonToken::BracketOpen)) => parse_json_array(lexer),
+		Some(Ok(JsonToken::BraceOpen)) => parse_json_object(lexer),
+		Some(_) => Err(invalid(lexer, "expected a value"))?,
+		None => Err(invalid(lexer, "unexpected end of input"))?,
+	}
+}
+
+fn parse_json_array<'source>(
+	lexer: &mut Lexer<'source, JsonToken<'source>>,
+) -> Result<Value<'source>> {
+	let mut items = Vec::new();
+	if lexer.clone().next() == Some(Ok(JsonToken::BracketClose)) {
+		lexer.next();
+		return Ok(Value::List(items));
+	}
+
+	loop {
+		items.push(parse_json_value(lexer)?);
+		match lexer.next() {
+			Some(Ok(JsonToken::Comma)) => (),
+			Some(Ok(JsonToken::BracketClose)) => return Ok(Value::List(items)),
+			_ => Err(invalid(lexer, "expected ',' or ']'"))?,
+		}
+	}
+}
+
+fn parse_json_object<'source>(
+	lexer: &mut Lexer<'source, JsonToken<'source>>,
+) -> Result<Value<'source>> {
+	let mut map = Map::new();
+	if lexer.clone().next() == Some(Ok(JsonToken::BraceClose)) {
+		lexer.next();
+		return Ok(Value::AttrSet(map));
+	}
+
+	loop {
+		let key = match lexer.next() {
+			Some(Ok(JsonToken::String(key))) => decode_string(key)?,
+			_ => Err(invalid(lexer, "expected a string key"))?,
+		};
+		match lexer.next() {
+			Some(Ok(JsonToken::Colon)) => (),
+			_ => Err(invalid(lexer, "expected ':'"))?,
+		}
+		map.insert(key, parse_json_value(lexer)?);
+
+		match lexer.next() {
+			Some(Ok(JsonToken::Comma)) => (),
+			Some(Ok(JsonToken::BraceClose)) => return Ok(Value::AttrSet(map)),
+			_ => Err(invalid(lexer, "expected ',' or '}'"))?,
+		}
+	}
+}
+
+/// Decodes the escapes of a quoted JSON string, borrowing it if it has none.
+fn decode_string(quoted: &str) -> Result<Cow<'_, str>> {
+	let inner = &quoted[1..quoted.len() - 1];
+	if !inner.contains('\\') {
+		return Ok(Cow::Borrowed(inner));
+	}
+
+	let mut decoded = String::with_capacity(inner.len());
+	let mut chars = inner.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			decoded.push(c);
+			continue;
+		}
+
+		match chars.next() {
+			Some('b') => decoded.push('\u{8}'),
+			Some('f') => decoded.push('\u{c}'),
+			Some('n') => decoded.push('\n'),
+			Some('r') => decoded.push('\r'),
+			Some('t') => decoded.push('\t'),
+			Some('u') => {
+				let high = read_hex4(&mut chars);
+				let code = if (0xD800..0xDC00).contains(&high) {
+					// A surrogate pair, spelled as two escapes. Only a low
+					// surrogate completes it, so anything else is left to be
+					// decoded by itself.
+					let mut lookahead = chars.clone();
+					let low = match (lookahead.next(), lookahead.next()) {
+						(Some('\\'), Some('u')) => read_hex4(&mut lookahead),
+						_ => 0,
+					};
+					if (0xDC00..0xE000).contains(&low) {
+						chars = lookahead;
+						0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+					} else {
+						0xFFFD
+					}
+				} else {
+					high
+				};
+				decoded.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+			}
+			// The lexer only admits valid escapes, leaving `"`, `\` and `/`.
+			Some(c) => decoded.push(c),
+			None => unreachable!("the lexer rejects a trailing backslash"),
+		}
+	}
+
+	Ok(Cow::Owned(decoded))
+}
+
+fn read_hex4(chars: &mut std::str::Chars) -> u32 {
+	let digits = chars.by_ref().take(4).collect::<String>();
+	u32::from_str_radix(&digits, 16).unwrap_or(0xFFFD)
+}
+
+fn invalid<'source>(
+	lexer: &Lexer<'source, JsonToken<'source>>,
+	message: &str,
+) -> JsonError {
+	JsonError::Invalid {
+		src: lexer.source().to_string(),
+		bad_bit: lexer.span().into(),
+		message: message.to_owned(),
+	}
+}
+
+impl<'source> Value<'source> {
+	/// Serializes the value as compact JSON, with attributes sorted by name.
+	///
+	/// References, such as those made by `inherit`, are looked up in
+	/// `scope`, an attrset standing in for the enclosing scope; without one
+	/// they cannot be exported.
+	pub fn to_json(&self, scope: Option<&Value<'source>>) -> Result<String> {
+		let mut out = String::new();
+		write_json(&mut out, self, scope)?;
+		Ok(out)
+	}
+}
+
+fn write_json<'source>(
+	out: &mut String,
+	value: &Value<'source>,
+	scope: Option<&Value<'source>>,
+) -> Result<()> {
+	match value {
+		Value::Null => out.push_str("null"),
+		Value::Bool(b) => out.push_str(&b.to_string()),
+		Value::Integer(n) => out.push_str(&n.to_string()),
+		Value::Float(n) if n.is_finite() => out.push_str(&n.to_string()),
+		Value::Float(_) => Err(JsonError::Unsupported("a non-finite float"))?,
+		Value::RawNumber(_) => Err(JsonError::Unsupported("a raw number"))?,
+		// Nix would copy the file to the store; the path is all there is here.
+		Value::String(s) | Value::Path(s) => write_json_string(out, s),
+		Value::List(items) => {
+			out.push('[');
+			for (i, item) in items.iter().enumerate() {
+				if i > 0 {
+					out.push(',');
+				}
+				write_json(out, item, scope)?;
+			}
+			out.push(']');
+		}
+		Value::AttrSet(map) | Value::RecAttrSet(map) => {
+			let mut keys = map.keys().collect::<Vec<_>>();
+			keys.sort();
+
+			out.push('{');
+			for (i, key) in keys.into_iter().enumerate() {
+				if i > 0 {
+					out.push(',');
+				}
+				write_json_string(out, key);
+				out.push(':');
+				write_json(out, &map[key], scope)?;
+			}
+			out.push('}');
+		}
+		Value::LetIn(..) => Err(JsonError::Unsupported("a let expression"))?,
+		Value::With(..) => Err(JsonError::Unsupported("a with expression"))?,
+		Value::If(..) => Err(JsonError::Unsupported("an if expression"))?,
+		Value::Select(..) => Err(JsonError::Unsupported("a selection"))?,
+		Value::BinOp(..) => Err(JsonError::Unsupported("an operation"))?,
+		Value::Lambda(..) => Err(JsonError::Unsupported("a function"))?,
+		Value::Apply(..) => Err(JsonError::Unsupported("a function call"))?,
+		Value::Interpolate(_) => {
+			Err(JsonError::Unsupported("an interpolated string"))?
+		}
+		Value::Ref(name) | Value::Inherit(name) => {
+			let Some(scope) = scope else {
+				Err(JsonError::UnresolvedRef(name.to_string()))?
+			};
+			let Some(resolved) = scope.get(name) else {
+				Err(JsonError::UndefinedRef(name.to_string()))?
+			};
+			// The scope's own values can't refer back to it.
+			write_json(out, resolved, None)?;
+		}
+	}
+
+	Ok(())
+}
+
+fn write_json_string(out: &mut String, s: &str) {
+	out.push('"');
+	for c in s.chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			c if c.is_control() => {
+				out.push_str(&format!("\\u{:04x}", c as u32))
+			}
+			c => out.push(c),
+		}
+	}
+	out.push('"');
+}
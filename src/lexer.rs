@@ -1,5 +1,6 @@
-use logos::Logos;
+use logos::{Lexer, Logos, Span};
 use miette::{Diagnostic, NamedSource, SourceSpan};
+use std::{cell::Cell, rc::Rc};
 use thiserror::Error;
 
 #[derive(Error, Debug, Diagnostic)]
@@ -17,38 +18,57 @@ pub struct LexError {
 	bad_bit: SourceSpan,
 }
 
-#[derive(Logos, Debug, PartialEq)]
+// Digit separators follow Rust's rules: any number of `_` may come after the
+// first digit, or after the prefix of a radix literal, as in `1_`, `1__2` or
+// `0x_ff`, but a decimal can't start with one, since `_1` is a name.
+#[derive(Logos, Debug, Clone, PartialEq)]
 #[logos(subpattern decimal = r"[0-9][_0-9]*")]
-#[logos(subpattern hex = r"[0-9a-fA-F][_0-9a-fA-F]*")]
-#[logos(subpattern octal = r"[0-7][_0-7]*")]
-#[logos(subpattern binary = r"[0-1][_0-1]*")]
+#[logos(subpattern hex = r"_*[0-9a-fA-F][_0-9a-fA-F]*")]
+#[logos(subpattern octal = r"_*[0-7][_0-7]*")]
+#[logos(subpattern binary = r"_*[0-1][_0-1]*")]
 #[logos(subpattern exp = r"[eE][+-]?[0-9][_0-9]*")]
 pub enum Token<'a> {
-	#[regex(r"[ ]", priority = 3)]
-	Space,
-
-	#[regex(r"//.*\n?", logos::skip)]
-	#[regex(r"[ \t\n\f]+", logos::skip)]
+	#[regex(r"#.*\n?", logos::skip)]
+	#[regex(r"[ \t\r\n\f]+", logos::skip)]
 	Error,
 
-	#[regex("(?&decimal)", |lex| lex.slice().parse::<i64>().unwrap())]
+	#[regex("(?&decimal)", |lex| parse_decimal(lex.slice()))]
 	Integer(i64),
 
-	#[regex("0[xX](?&hex)")]
-	HexInteger,
+	#[regex("0[xX](?&hex)", |lex| parse_radix(lex.slice(), 16))]
+	HexInteger(i64),
 
-	#[regex("0[oO](?&octal)")]
-	OctalInteger,
+	#[regex("0[oO](?&octal)", |lex| parse_radix(lex.slice(), 8))]
+	OctalInteger(i64),
 
-	#[regex("0[bB](?&binary)")]
-	BinaryInteger,
+	#[regex("0[bB](?&binary)", |lex| parse_radix(lex.slice(), 2))]
+	BinaryInteger(i64),
 
-	#[regex(r#"[+-]?(((?&decimal)\.(?&decimal)?(?&exp)?[fFdD]?)|(\.(?&decimal)(?&exp)?[fFdD]?)|((?&decimal)(?&exp)[fFdD]?)|((?&decimal)(?&exp)?[fFdD]))"#, |lex| lex.slice().parse::<f64>().unwrap())]
+	#[regex(r#"(((?&decimal)\.(?&decimal)?(?&exp)?[fFdD]?)|(\.(?&decimal)(?&exp)?[fFdD]?)|((?&decimal)(?&exp)[fFdD]?)|((?&decimal)(?&exp)?[fFdD]))"#, |lex| parse_decimal(lex.slice()))]
 	Float(f64),
 
 	#[regex(r"0[xX](((?&hex))|((?&hex)\.)|((?&hex)?\.(?&hex)))[pP][+-]?(?&decimal)[fFdD]?")]
 	HexFloat(&'a str),
 
+	/// An integer selected from, such as `1.foo`, which would otherwise lex
+	/// as the float `1.` or `1.f` and a name.
+	#[regex(r"(?&decimal)\.(\p{XID_Start}|_)\p{XID_Continue}*", priority = 1)]
+	IntegerSelect(&'a str),
+
+	/// A numeric literal running straight into identifier characters, such
+	/// as `1abc`, `0x1g` or `1.0e`, or with an exponent lacking digits, such
+	/// as `1e+`. This only wins when no well-formed literal covers as much
+	/// input.
+	#[regex(
+		r"[0-9][_0-9]*(\.[0-9][_0-9]*)?[a-zA-Z_]\p{XID_Continue}*",
+		priority = 0
+	)]
+	#[regex(
+		r"((?&decimal)(\.(?&decimal))?|\.(?&decimal))[eE][+-]?",
+		priority = 1
+	)]
+	InvalidNumber(&'a str),
+
 	#[token("false", |_| false)]
 	#[token("true", |_| true)]
 	Bool(bool),
@@ -65,12 +85,33 @@ pub enum Token<'a> {
 	#[token("]")]
 	BracketClose,
 
+	#[token("(")]
+	ParenOpen,
+
+	#[token(")")]
+	ParenClose,
+
 	#[token(":")]
 	Colon,
 
 	#[token(",")]
 	Comma,
 
+	/// The select operator, as in `a.b`. Runs of dots lex as the longest
+	/// token that matches, so `...` is an ellipsis and `../x` a path, with
+	/// anything else, such as `a..b`, left as single dots.
+	#[token(".")]
+	Dot,
+
+	#[token("...")]
+	Ellipsis,
+
+	#[token("?")]
+	Question,
+
+	#[token("@")]
+	At,
+
 	#[token("null")]
 	Null,
 
@@ -80,12 +121,300 @@ pub enum Token<'a> {
 	#[token("in")]
 	In,
 
+	#[token("inherit")]
+	Inherit,
+
+	#[token("with")]
+	With,
+
+	#[token("if")]
+	If,
+
+	#[token("then")]
+	Then,
+
+	#[token("else")]
+	Else,
+
 	#[token("=")]
 	Equals,
 
+	#[token("==")]
+	EqEq,
+
+	/// Not Nix, but lexed so that a function written `x => y`, as in other
+	/// languages, gets a helpful error.
+	#[token("=>")]
+	FatArrow,
+
 	#[token(";")]
 	SemiColon,
 
+	#[token("+")]
+	Plus,
+
+	#[token("-")]
+	Minus,
+
+	#[token("*")]
+	Star,
+
+	#[token("/")]
+	Slash,
+
+	#[token("++")]
+	Concat,
+
+	#[token("//")]
+	Update,
+
+	/// A double-quoted string, escapes and all.
+	#[regex(r#""([^"\\]|\\(.|\n))*""#)]
+	String(&'a str),
+
+	/// An indented string, `''...''`, escapes and all. Within it, `'''` is an
+	/// escaped `''`, and `''` escapes `$` and `\` escapes.
+	#[regex(r"''([^']|'[^']|'''|''\$|''\\(.|\n))*''")]
+	IndentedString(&'a str),
+
+	/// A path that is absolute, as in `/etc/x`, relative to the file, as in
+	/// `./x`, `../x` or `x/y`, or relative to the home directory, as in `~/x`.
+	/// It must contain a slash, so a bare `x` is still a name, and only letters,
+	/// digits and `_` may come before the first one, so `a-b/c` is still a
+	/// subtraction. In `a.b/c`, though, `b/c` is a path, which can't be
+	/// selected.
+	#[regex(r"(\.\.?|~|[a-zA-Z0-9_]+)?(/[a-zA-Z0-9._\-+]+)+")]
+	Path(&'a str),
+
 	#[regex(r"(\p{XID_Start}|_)\p{XID_Continue}*")]
 	Identifier(&'a str),
 }
+
+impl Token<'_> {
+	/// The text of a keyword token, such as `let`, or `None` for any other
+	/// token. `rec` and `or` aren't keyword tokens, since they're keywords
+	/// only in some places.
+	pub fn keyword(&self) -> Option<&'static str> {
+		match self {
+			Token::Let => Some("let"),
+			Token::In => Some("in"),
+			Token::Inherit => Some("inherit"),
+			Token::With => Some("with"),
+			Token::If => Some("if"),
+			Token::Then => Some("then"),
+			Token::Else => Some("else"),
+			_ => None,
+		}
+	}
+}
+
+/// Parses a decimal literal, ignoring digit separators, those in an
+/// exponent such as `1e1_0` included, and any type suffix. An integer too
+/// large for an `i64` is a lexing error.
+fn parse_decimal<T: std::str::FromStr>(slice: &str) -> Option<T> {
+	let digits = slice
+		.trim_end_matches(['f', 'F', 'd', 'D'])
+		.replace('_', "");
+	digits.parse().ok()
+}
+
+/// Parses an integer literal after its two-character prefix, such as `0x`,
+/// ignoring digit separators.
+fn parse_radix(slice: &str, radix: u32) -> Option<i64> {
+	i64::from_str_radix(&slice[2..].replace('_', ""), radix).ok()
+}
+
+/// A source of tokens the parser can consume and look ahead in by cloning.
+pub trait TokenStream<'source>: Clone {
+	fn next(&mut self) -> Option<Result<Token<'source>, ()>>;
+
+	/// The span of the token most recently returned by `next`, empty at the
+	/// start of the source before the first one, and at the end of it once
+	/// `next` has returned `None`.
+	fn span(&self) -> Span;
+
+	/// The source text the tokens were lexed from.
+	fn source(&self) -> &'source str;
+}
+
+impl<'source> TokenStream<'source> for Lexer<'source, Token<'source>> {
+	fn next(&mut self) -> Option<Result<Token<'source>, ()>> {
+		Iterator::next(self)
+	}
+
+	fn span(&self) -> Span {
+		Lexer::span(self)
+	}
+
+	fn source(&self) -> &'source str {
+		Lexer::source(self)
+	}
+}
+
+/// Tokens that were already lexed, e.g. for highlighting, replayed so they
+/// can be parsed without lexing the source again.
+#[derive(Debug, Clone)]
+pub struct TokenSlice<'t, 'source> {
+	source: &'source str,
+	tokens: &'t [(Token<'source>, Span)],
+	position: usize,
+}
+
+impl<'t, 'source> TokenSlice<'t, 'source> {
+	pub fn new(
+		source: &'source str,
+		tokens: &'t [(Token<'source>, Span)],
+	) -> Self {
+		Self {
+			source,
+			tokens,
+			position: 0,
+		}
+	}
+}
+
+impl<'t, 'source> TokenStream<'source> for TokenSlice<'t, 'source> {
+	fn next(&mut self) -> Option<Result<Token<'source>, ()>> {
+		// Stepping past the last token marks the end as reached, for `span`.
+		self.position = (self.position + 1).min(self.tokens.len() + 1);
+		let (token, _) = self.tokens.get(self.position - 1)?;
+		Some(Ok(token.clone()))
+	}
+
+	fn span(&self) -> Span {
+		match self.position.checked_sub(1) {
+			Some(last) if last < self.tokens.len() => {
+				self.tokens[last].1.clone()
+			}
+			Some(_) => self.source.len()..self.source.len(),
+			None => 0..0,
+		}
+	}
+
+	fn source(&self) -> &'source str {
+		self.source
+	}
+}
+
+/// Tokens from another stream, cut off at the first one longer than a
+/// limit, after which the stream ends. Identifiers and numbers are measured
+/// before they're lexed, since in a debug build lexing a long enough one
+/// overflows the stack.
+#[derive(Debug, Clone)]
+pub(crate) struct LimitedTokens<L> {
+	tokens: L,
+	max: usize,
+	/// Where the run measured last ends, so no run is measured twice.
+	measured: usize,
+	/// The span of the token that cut the stream off, shared with every
+	/// clone made to look ahead.
+	cut: Rc<Cell<Option<(usize, usize)>>>,
+}
+
+impl<'source, L: TokenStream<'source>> LimitedTokens<L> {
+	pub(crate) fn new(tokens: L, max: usize) -> Self {
+		Self {
+			tokens,
+			max,
+			measured: 0,
+			cut: Rc::default(),
+		}
+	}
+
+	/// The span of the token that was too long, once one has been reached.
+	pub(crate) fn cut(&self) -> Option<Span> {
+		self.cut.get().map(|(start, end)| start..end)
+	}
+
+	pub(crate) fn into_inner(self) -> L {
+		self.tokens
+	}
+}
+
+impl<'source, L: TokenStream<'source>> TokenStream<'source>
+	for LimitedTokens<L>
+{
+	fn next(&mut self) -> Option<Result<Token<'source>, ()>> {
+		if self.cut.get().is_some() {
+			return None;
+		}
+
+		let source = self.tokens.source();
+		let start = skip_trivia(source, self.tokens.span().end);
+		if start >= self.measured {
+			self.measured = word_end(source, start);
+			if self.measured - start > self.max {
+				self.cut.set(Some((start, self.measured)));
+				return None;
+			}
+		}
+
+		let token = self.tokens.next()?;
+		let span = self.tokens.span();
+		if span.len() > self.max {
+			self.cut.set(Some((span.start, span.end)));
+			return None;
+		}
+		Some(token)
+	}
+
+	fn span(&self) -> Span {
+		self.cut().unwrap_or_else(|| self.tokens.span())
+	}
+
+	fn source(&self) -> &'source str {
+		self.tokens.source()
+	}
+}
+
+/// Where the next token after `position` starts, past any whitespace and
+/// comments.
+fn skip_trivia(source: &str, mut position: usize) -> usize {
+	let bytes = source.as_bytes();
+	while let Some(&byte) = bytes.get(position) {
+		match byte {
+			b' ' | b'\t' | b'\r' | b'\n' | b'\x0c' => position += 1,
+			b'#' => {
+				position = source[position..]
+					.find('\n')
+					.map_or(source.len(), |offset| position + offset + 1);
+			}
+			_ => break,
+		}
+	}
+	position
+}
+
+/// Where the run of characters starting at `start` that could all belong
+/// to one identifier or number ends. This can overestimate, as with `1e`
+/// before a name, but never falls short of the token actually there.
+fn word_end(source: &str, start: usize) -> usize {
+	let bytes = source.as_bytes();
+	// Only a number can have a `.`, once, or a sign after its exponent.
+	let number = matches!(bytes.get(start), Some(b'0'..=b'9' | b'.'));
+	let mut dot = false;
+	let mut end = start;
+	while let Some(&byte) = bytes.get(end) {
+		let part = match byte {
+			b'.' if number && !dot => {
+				dot = true;
+				true
+			}
+			b'+' | b'-' => {
+				number
+					&& end > start && matches!(
+					bytes[end - 1],
+					b'e' | b'E' | b'p' | b'P'
+				)
+			}
+			_ => {
+				byte.is_ascii_alphanumeric() || byte == b'_' || !byte.is_ascii()
+			}
+		};
+		if !part {
+			break;
+		}
+		end += 1;
+	}
+	end
+}
@@ -1,2 +1,47 @@
+use miette::{
+	Diagnostic, GraphicalReportHandler, GraphicalTheme, IntoDiagnostic, Result,
+	WrapErr,
+};
+use value::{OwnedValue, Value};
+
+pub mod diff;
+pub mod eval;
+pub mod json;
 pub mod lexer;
+pub mod nix;
 pub mod parser;
+pub mod value;
+
+pub use json::from_json_str;
+
+/// Renders a diagnostic, such as a [`parser::ParseError`] or
+/// [`lexer::LexError`], the way miette prints it but without colors, for
+/// embedders that display it themselves.
+pub fn render_diagnostic(err: &dyn Diagnostic) -> String {
+	let mut out = String::new();
+	GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor())
+		.with_links(false)
+		.render_report(&mut out, err)
+		.expect("writing to a String can't fail");
+	out
+}
+
+/// Parses and canonicalizes each of `files`, then deep-merges them in
+/// order, so later files override earlier ones, as in a base config followed
+/// by overrides. Canonicalizing first means a file whose attrset is wrapped
+/// in a `let` or `with` is merged as the attrset it evaluates to.
+pub fn parse_and_merge(files: &[&str]) -> Result<OwnedValue> {
+	let mut merged = Value::AttrSet(Default::default());
+	for &file in files {
+		let src = std::fs::read_to_string(file)
+			.into_diagnostic()
+			.wrap_err_with(|| format!("failed to read {file}"))?;
+		let value = parser::Parser::default()
+			.parse(&src, file)
+			.wrap_err_with(|| format!("failed to parse {file}"))?
+			.canonicalize()
+			.wrap_err_with(|| format!("failed to evaluate {file}"))?;
+		merged = merged.deep_merge(value.into_owned());
+	}
+	Ok(merged)
+}
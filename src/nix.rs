@@ -0,0 +1,317 @@
+use crate::eval::BinOp;
+use crate::lexer::Token;
+use crate::value::{Map, Pattern, Value};
+use logos::Logos;
+
+impl Value<'_> {
+	/// Writes the value back out as Nix source that parses to the same
+	/// value, with attributes sorted by name and each binding on its own
+	/// line, indented by two spaces.
+	///
+	/// Parentheses are only added where the value wouldn't parse back the
+	/// same without them, such as around an operation that binds less
+	/// tightly than the one it's an operand of, or around `if` and the other
+	/// expressions that extend as far to the right as they can.
+	///
+	/// Operations on literals were already folded by the parser, so they're
+	/// written as their results: `{ x = 1 + 2; }` comes back as
+	/// `{ x = 3; }`, and only operations on names keep their operators.
+	pub fn to_nix(&self) -> String {
+		let mut out = String::new();
+		write_nix(&mut out, self, 0);
+		out
+	}
+
+	/// Like [`to_nix`](Self::to_nix), but as the contents of a whole file,
+	/// ending in a single newline.
+	pub fn to_nix_file(&self) -> String {
+		let mut out = self.to_nix();
+		out.push('\n');
+		out
+	}
+}
+
+fn write_nix(out: &mut String, value: &Value, indent: usize) {
+	match value {
+		Value::Null => out.push_str("null"),
+		Value::Bool(b) => out.push_str(&b.to_string()),
+		// Its magnitude is one too large to be written as a literal.
+		Value::Integer(i64::MIN) => out.push_str("(-9223372036854775807 - 1)"),
+		Value::Integer(n) => out.push_str(&n.to_string()),
+		// Debug formatting keeps the `.` of `1.0`, which makes it a float.
+		Value::Float(n) => out.push_str(&format!("{n:?}")),
+		Value::RawNumber(s) | Value::Path(s) => out.push_str(s),
+		Value::String(s) => write_string(out, s),
+		Value::List(items) if items.is_empty() => out.push_str("[ ]"),
+		Value::List(items) if items.iter().all(Value::is_scalar) => {
+			out.push('[');
+			for item in items {
+				out.push(' ');
+				write_operand(out, item, indent);
+			}
+			out.push_str(" ]");
+		}
+		Value::List(items) => {
+			out.push_str("[\n");
+			for item in items {
+				pad(out, indent + 1);
+				write_operand(out, item, indent + 1);
+				out.push('\n');
+			}
+			pad(out, indent);
+			out.push(']');
+		}
+		Value::AttrSet(map) | Value::RecAttrSet(map) => {
+			if matches!(value, Value::RecAttrSet(_)) {
+				out.push_str("rec ");
+			}
+			if map.is_empty() {
+				out.push_str("{ }");
+			} else {
+				out.push_str("{\n");
+				write_bindings(out, map, indent + 1);
+				pad(out, indent);
+				out.push('}');
+			}
+		}
+		Value::LetIn(bindings, body) => {
+			out.push_str("let\n");
+			write_bindings(out, bindings, indent + 1);
+			pad(out, indent);
+			out.push_str("in ");
+			write_nix(out, body, indent);
+		}
+		Value::Ref(name) | Value::Inherit(name) => out.push_str(name),
+		Value::With(scope, body) => {
+			out.push_str("with ");
+			write_nix(out, scope, indent);
+			out.push_str("; ");
+			write_nix(out, body, indent);
+		}
+		Value::If(cond, then, otherwise) => {
+			out.push_str("if ");
+			write_nix(out, cond, indent);
+			out.push_str(" then ");
+			write_nix(out, then, indent);
+			out.push_str(" else ");
+			write_nix(out, otherwise, indent);
+		}
+		Value::Select(base, path, default) => {
+			// A select of a select would merge into one longer path, and the
+			// names selected from a path would lex as more of the path.
+			let bare = is_operand(base)
+				&& !matches!(**base, Value::Select(..) | Value::Path(_));
+			write_parenthesized(out, base, indent, !bare);
+			for name in path {
+				out.push('.');
+				write_name(out, name);
+			}
+			if let Some(default) = default {
+				out.push_str(" or ");
+				write_operand(out, default, indent);
+			}
+		}
+		Value::BinOp(op, lhs, rhs) => {
+			let lhs_parens = needs_parens(*op, lhs, false);
+			write_parenthesized(out, lhs, indent, lhs_parens);
+			out.push_str(match op {
+				BinOp::Add => " + ",
+				BinOp::Sub => " - ",
+				BinOp::Mul => " * ",
+				BinOp::Div => " / ",
+				BinOp::Concat => " ++ ",
+				BinOp::Eq => " == ",
+				BinOp::Update => " // ",
+			});
+			let rhs_parens = needs_parens(*op, rhs, true);
+			write_parenthesized(out, rhs, indent, rhs_parens);
+		}
+		Value::Lambda(pattern, body) => {
+			write_pattern(out, pattern, indent);
+			out.push_str(": ");
+			write_nix(out, body, indent);
+		}
+		Value::Apply(function, arg) => {
+			let bare =
+				is_operand(function) || matches!(**function, Value::Apply(..));
+			write_parenthesized(out, function, indent, !bare);
+			out.push(' ');
+			write_operand(out, arg, indent);
+		}
+		Value::Interpolate(parts) => {
+			out.push('"');
+			for part in parts {
+				match part {
+					Value::String(s) => write_string_contents(out, s),
+					part => {
+						out.push_str("${");
+						write_nix(out, part, indent);
+						out.push('}');
+					}
+				}
+			}
+			out.push('"');
+		}
+	}
+}
+
+/// Writes the bindings of an attrset or `let`, one to a line, with those
+/// made by `inherit` written as such.
+fn write_bindings(out: &mut String, map: &Map, indent: usize) {
+	let mut keys = map.keys().collect::<Vec<_>>();
+	keys.sort();
+	for key in keys {
+		pad(out, indent);
+		match &map[key] {
+			Value::Inherit(name) if name == key => {
+				out.push_str("inherit ");
+				out.push_str(name);
+			}
+			value => {
+				write_name(out, key);
+				out.push_str(" = ");
+				write_nix(out, value, indent);
+			}
+		}
+		out.push_str(";\n");
+	}
+}
+
+fn write_pattern(out: &mut String, pattern: &Pattern, indent: usize) {
+	let (params, ellipsis, bind) = match pattern {
+		Pattern::Ident(name) => return out.push_str(name),
+		Pattern::Set {
+			params,
+			ellipsis,
+			bind,
+		} => (params, *ellipsis, bind),
+	};
+
+	if let Some(bind) = bind {
+		out.push_str(bind);
+		out.push('@');
+	}
+	if params.is_empty() && !ellipsis {
+		return out.push_str("{ }");
+	}
+	out.push('{');
+	for (i, (name, default)) in params.iter().enumerate() {
+		out.push_str(if i > 0 { ", " } else { " " });
+		out.push_str(name);
+		if let Some(default) = default {
+			out.push_str(" ? ");
+			write_nix(out, default, indent);
+		}
+	}
+	if ellipsis {
+		out.push_str(if params.is_empty() { " ..." } else { ", ..." });
+	}
+	out.push_str(" }");
+}
+
+/// Writes a list element, argument or `or` default, which must be a single
+/// operand.
+fn write_operand(out: &mut String, value: &Value, indent: usize) {
+	write_parenthesized(out, value, indent, !is_operand(value));
+}
+
+fn write_parenthesized(
+	out: &mut String,
+	value: &Value,
+	indent: usize,
+	parens: bool,
+) {
+	if parens {
+		out.push('(');
+	}
+	write_nix(out, value, indent);
+	if parens {
+		out.push(')');
+	}
+}
+
+/// Whether the value parses as a single operand without parentheses, so
+/// that it can be a list element or argument. A negative number is an
+/// operation on the number, so it isn't one.
+fn is_operand(value: &Value) -> bool {
+	match value {
+		Value::Integer(n) => *n >= 0,
+		Value::Float(n) => n.is_sign_positive(),
+		Value::Null
+		| Value::Bool(_)
+		| Value::RawNumber(_)
+		| Value::String(_)
+		| Value::Path(_)
+		| Value::List(_)
+		| Value::AttrSet(_)
+		| Value::RecAttrSet(_)
+		| Value::Ref(_)
+		| Value::Inherit(_)
+		| Value::Select(..)
+		| Value::Interpolate(_) => true,
+		Value::LetIn(..)
+		| Value::With(..)
+		| Value::If(..)
+		| Value::BinOp(..)
+		| Value::Lambda(..)
+		| Value::Apply(..) => false,
+	}
+}
+
+/// Whether `operand` needs parentheses as an operand of `op`, on its right
+/// if `rhs` is set.
+fn needs_parens(op: BinOp, operand: &Value, rhs: bool) -> bool {
+	match operand {
+		Value::BinOp(inner, ..) => {
+			// Nix doesn't chain `==`, even though it could be parsed.
+			inner.precedence() < op.precedence()
+				|| (inner.precedence() == op.precedence()
+					&& (rhs != op.is_right_associative() || op == BinOp::Eq))
+		}
+		// These extend as far to the right as they can.
+		Value::LetIn(..)
+		| Value::With(..)
+		| Value::If(..)
+		| Value::Lambda(..) => true,
+		_ => false,
+	}
+}
+
+/// Writes an attribute name, quoted unless it lexes as a name by itself.
+fn write_name(out: &mut String, name: &str) {
+	let mut lexer = Token::lexer(name);
+	let plain = matches!(
+		lexer.next(),
+		Some(Ok(Token::Identifier(_) | Token::Bool(_) | Token::Null))
+	) && lexer.slice() == name;
+	if plain {
+		out.push_str(name);
+	} else {
+		write_string(out, name);
+	}
+}
+
+fn write_string(out: &mut String, s: &str) {
+	out.push('"');
+	write_string_contents(out, s);
+	out.push('"');
+}
+
+fn write_string_contents(out: &mut String, s: &str) {
+	let mut chars = s.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			'$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+			c => out.push(c),
+		}
+	}
+}
+
+fn pad(out: &mut String, indent: usize) {
+	out.push_str(&"  ".repeat(indent));
+}
@@ -1,16 +1,16 @@
-use crate::lexer::Token;
-use logos::Lexer;
-use miette::{diagnostic, Diagnostic, NamedSource, Result, SourceSpan};
-use std::collections::HashMap;
+use crate::{
+	eval::{self, BinOp},
+	lexer::{LimitedTokens, Token, TokenSlice, TokenStream},
+	value::{Map, Pattern, Value},
+};
+use logos::{Logos, Span};
+use miette::{Diagnostic, NamedSource, Result, SourceSpan};
+use std::{borrow::Cow, collections::HashMap};
 use thiserror::Error;
 
 #[derive(Error, Debug, Diagnostic)]
 #[error("parse error: {message}")]
-#[diagnostic(
-	code(nixc::parser::error),
-	url(docsrs),
-	help("try doing it better next time?")
-)]
+#[diagnostic(code(nixc::parser::error), url(docsrs))]
 pub struct ParseError {
 	// The Source that we're gonna be printing snippets out of.
 	// This can be a String if you don't have or care about file names.
@@ -21,47 +21,902 @@ pub struct ParseError {
 	bad_bit: SourceSpan,
 
 	message: String,
+
+	#[help]
+	help: Option<String>,
+}
+
+/// A closing delimiter that doesn't match the opening one it would close,
+/// as in `[ 1 }`.
+#[derive(Error, Debug, Diagnostic)]
+#[error(
+	"parse error: mismatched delimiter: expected '{expected}' to close \
+	 '{open}' but found '{found}'"
+)]
+#[diagnostic(code(nixc::parser::mismatched_delimiter), url(docsrs))]
+pub struct MismatchedDelimiter {
+	#[source_code]
+	src: NamedSource<String>,
+
+	#[label("'{open}' opened here")]
+	open_span: SourceSpan,
+
+	#[label("closed by '{found}' here")]
+	found_span: SourceSpan,
+
+	open: char,
+	expected: char,
+	found: char,
+}
+
+/// A line indented with a tab, reported when [`ParserConfig::lint_tabs`]
+/// is set.
+#[derive(Error, Debug, Diagnostic)]
+#[error("line is indented with a tab")]
+#[diagnostic(
+	code(nixc::parser::tab_indentation),
+	url(docsrs),
+	severity(Warning),
+	help("indent with spaces instead")
+)]
+pub struct TabIndentation {
+	#[source_code]
+	src: NamedSource<String>,
+
+	#[label("This indentation here")]
+	indent: SourceSpan,
 }
 
-/// Represent any valid JSON value.
+/// Options controlling what the parser accepts.
 #[derive(Debug, Clone)]
-pub enum Value<'source> {
-	Null,
-	Bool(bool),
-	Integer(i64),
-	Float(f64),
-	String(&'source str),
-	List(Vec<Value<'source>>),
-	AttrSet(HashMap<&'source str, Value<'source>>),
-	LetIn(HashMap<&'source str, Value<'source>>, Box<Value<'source>>),
+pub struct ParserConfig {
+	/// Accept the final binding of an attrset without its `;`, as in
+	/// `{ a = 1 }`, which eases migrating from JSON. Off by default, since
+	/// Nix requires it.
+	pub lenient: bool,
+
+	/// Keep number literals as the text they were written as, in
+	/// [`Value::RawNumber`], so tools can re-emit `0xFF` or `1_000`
+	/// unchanged.
+	pub raw_numbers: bool,
+
+	/// The longest a single token may be, in bytes, so that adversarial
+	/// input can't make the parser copy around huge slices. Defaults to 4
+	/// KiB, well short of the length at which lexing an identifier or a
+	/// number overflows a thread's stack in a debug build. Checked by every
+	/// entry point, identifiers and numbers before they're lexed.
+	pub max_token_length: usize,
+
+	/// What to do with an escape Nix doesn't define, such as `\q`.
+	pub escapes: StringEscapePolicy,
+
+	/// Whether keywords such as `let` or `if` may name attributes without
+	/// being quoted.
+	pub keywords: KeywordPolicy,
+
+	/// Warn about every line indented with a tab, for teams that indent
+	/// with spaces; see [`Parser::warnings`].
+	pub lint_tabs: bool,
+
+	/// Keep the `#` comments around each attribute, and whether a blank line
+	/// comes before it, for [`Parser::comments`], so a formatter can put
+	/// them back.
+	pub keep_comments: bool,
+}
+
+/// How a string treats a backslash before a character with no escape.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringEscapePolicy {
+	/// Report it as an error.
+	#[default]
+	Strict,
+	/// Keep the backslash and the character as they are.
+	Passthrough,
+}
+
+/// Whether keywords may be used as attribute names, as in `{ if = 1; }`.
+///
+/// `inherit` always starts an `inherit`, and `in` always ends the bindings
+/// of a `let`, so neither can name a binding there either way. `rec` and
+/// `or` are only keywords where they're used as such, so they can always
+/// name attributes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeywordPolicy {
+	/// Report a keyword used as a name as an error, like Nix.
+	#[default]
+	Reserved,
+	/// Accept keywords as names.
+	AsNames,
 }
 
+impl Default for ParserConfig {
+	fn default() -> Self {
+		Self {
+			lenient: false,
+			raw_numbers: false,
+			max_token_length: 4 * 1024,
+			escapes: StringEscapePolicy::Strict,
+			keywords: KeywordPolicy::Reserved,
+			lint_tabs: false,
+			keep_comments: false,
+		}
+	}
+}
+
+#[derive(Default)]
 pub struct Parser<'a> {
 	pub file_name: &'a str,
+	pub config: ParserConfig,
+	/// The constructs being parsed, outermost first, for the breadcrumb
+	/// shown with errors.
+	context: Vec<Context>,
+	validator: Option<Box<Validator<'a>>>,
+	/// Errors skipped over by [`Parser::parse_with_recovery`].
+	recovered: Option<Vec<miette::Report>>,
+	comments: HashMap<Vec<String>, Comments>,
+	warnings: Vec<miette::Report>,
+}
+
+/// The comments and blank lines around an attribute, kept when
+/// [`ParserConfig::keep_comments`] is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Comments {
+	/// The comment lines directly above the attribute, in order.
+	pub leading: Vec<String>,
+	/// The comment after the attribute on the same line, as in
+	/// `a = 1; # the a value`.
+	pub trailing: Option<String>,
+	/// Whether the attribute, or the comments above it, come after a blank
+	/// line. Any number of blank lines counts as one, so a formatter keeps
+	/// the separation without keeping every line of it.
+	pub blank_line_before: bool,
+}
+
+/// A check run on each value as soon as it has been parsed, which rejects
+/// it by returning a message.
+pub type Validator<'a> =
+	dyn for<'source> Fn(&Value<'source>, SourceSpan) -> Result<(), String> + 'a;
+
+/// A construct the parser is inside of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Context {
+	List,
+	Attribute(String),
+	LetBinding(String),
+}
+
+impl std::fmt::Display for Context {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Context::List => write!(f, "in list"),
+			Context::Attribute(name) => write!(f, "in attribute '{name}'"),
+			Context::LetBinding(name) => write!(f, "in let binding '{name}'"),
+		}
+	}
 }
 
 impl<'a> Parser<'a> {
-	pub fn parse_value<'source>(
+	/// Parses the whole of `src` as a single value. The same parser can be
+	/// reused for any number of independent sources.
+	pub fn parse<'source>(
+		&mut self,
+		src: &'source str,
+		file_name: &'a str,
+	) -> Result<Value<'source>> {
+		self.file_name = file_name;
+		self.context.clear();
+		self.comments.clear();
+		self.warnings.clear();
+
+		let mut lexer = self.limited(Token::lexer(src));
+		if self.config.lint_tabs {
+			self.lint_tabs(&lexer);
+		}
+		let result = self
+			.parse_expr(&mut lexer)
+			.map_err(|err| self.attach_context(err))
+			.and_then(|value| {
+				self.expect_end(&mut lexer)?;
+				Ok(value)
+			});
+		self.check_cut(&lexer, result)
+	}
+
+	/// Runs `validator` on every value as it is parsed: the top-level value,
+	/// the value of each binding and each element of a list. A rejection
+	/// becomes a parse error pointing at the value, so schema violations
+	/// are reported with their location.
+	pub fn validate_with(
+		&mut self,
+		validator: impl for<'source> Fn(&Value<'source>, SourceSpan) -> Result<(), String>
+			+ 'a,
+	) {
+		self.validator = Some(Box::new(validator));
+	}
+
+	/// The warnings from the lints enabled in [`ParserConfig`] for the last
+	/// parse.
+	pub fn warnings(&self) -> &[miette::Report] {
+		&self.warnings
+	}
+
+	/// The comments kept by the last parse, by the path of the attribute
+	/// they belong to, such as `["a", "b"]` for `a.b`. Only attributes
+	/// reachable through attrsets have a path, so those in lists or `let`
+	/// bindings aren't included, nor are attributes with neither comments
+	/// nor a blank line before them.
+	pub fn comments(&self) -> &HashMap<Vec<String>, Comments> {
+		&self.comments
+	}
+
+	/// Like [`Parser::parse`], but carries on past a binding that fails to
+	/// parse, by skipping to the `;` that ends it, so that every such error
+	/// is reported at once rather than only the first one.
+	pub fn parse_with_recovery<'source>(
+		&mut self,
+		src: &'source str,
+		file_name: &'a str,
+	) -> std::result::Result<Value<'source>, Vec<miette::Report>> {
+		self.recovered = Some(Vec::new());
+		let result = self.parse(src, file_name);
+		let mut errors = self.recovered.take().unwrap_or_default();
+
+		match result {
+			Ok(value) if errors.is_empty() => Ok(value),
+			Ok(_) => Err(errors),
+			Err(err) => {
+				errors.push(err);
+				Err(errors)
+			}
+		}
+	}
+
+	/// Parses a top-level attrset one binding at a time, so a consumer
+	/// that only needs some attributes can stop early without the rest
+	/// being parsed, or lexed: each token's length is checked as it's
+	/// reached.
+	pub fn attr_stream<'p, 'source, L: TokenStream<'source>>(
+		&'p mut self,
+		lexer: L,
+	) -> AttrStream<'p, 'a, 'source, L> {
+		self.context.clear();
+		AttrStream {
+			lexer: self.limited(lexer),
+			parser: self,
+			opening: None,
+			pending: Vec::new(),
+			done: false,
+		}
+	}
+
+	/// Parses a value from tokens that were already lexed from `source`,
+	/// rejecting any left after it like [`Parser::parse`] does.
+	pub fn parse_value_from_tokens<'source>(
+		&mut self,
+		source: &'source str,
+		tokens: &[(Token<'source>, Span)],
+	) -> Result<Value<'source>> {
+		let mut tokens = self.limited(TokenSlice::new(source, tokens));
+		self.context.clear();
+		let result = self
+			.parse_expr(&mut tokens)
+			.map_err(|err| self.attach_context(err))
+			.and_then(|value| {
+				self.expect_end(&mut tokens)?;
+				Ok(value)
+			});
+		self.check_cut(&tokens, result)
+	}
+
+	/// Rejects anything left after the top-level value.
+	fn expect_end<'source, L: TokenStream<'source>>(
+		&self,
+		lexer: &mut L,
+	) -> Result<()> {
+		match lexer.next() {
+			None => Ok(()),
+			Some(Ok(Token::ParenClose)) => Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: "unmatched closing parenthesis".to_owned(),
+				help: None,
+			})?,
+			Some(Ok(Token::BracketClose)) => Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: "unmatched closing bracket".to_owned(),
+				help: None,
+			})?,
+			Some(Ok(Token::BraceClose)) => Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: "unmatched closing brace".to_owned(),
+				help: None,
+			})?,
+			Some(Ok(Token::Identifier(name))) => Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: format!(
+					"unexpected identifier `{name}` after the top-level \
+					 expression"
+				),
+				help: Some(
+					"a file holds a single expression, and the one before \
+					 this isn't a function it could be passed to"
+						.to_owned(),
+				),
+			})?,
+			Some(_) => Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: "unexpected token after the top-level value"
+					.to_owned(),
+				help: None,
+			})?,
+		}
+	}
+
+	/// Adds where in the input a parse error happened to its help, as a
+	/// breadcrumb such as "in list → in attribute 'a'". The context is left
+	/// as it was when the error was raised, since nothing pops it on the way
+	/// out.
+	fn attach_context(&mut self, err: miette::Report) -> miette::Report {
+		let err = self.with_breadcrumb(err);
+		self.context.clear();
+		err
+	}
+
+	fn with_breadcrumb(&self, err: miette::Report) -> miette::Report {
+		if self.context.is_empty() {
+			return err;
+		}
+		let mut err = match err.downcast::<ParseError>() {
+			Ok(err) => err,
+			Err(err) => return err,
+		};
+
+		let breadcrumb = self
+			.context
+			.iter()
+			.map(Context::to_string)
+			.collect::<Vec<_>>()
+			.join(" → ");
+		err.help = Some(match err.help {
+			Some(help) => format!("{help}\n{breadcrumb}"),
+			None => breadcrumb,
+		});
+		err.into()
+	}
+
+	/// Wraps `tokens` to end at the first token longer than
+	/// [`ParserConfig::max_token_length`].
+	fn limited<'source, L: TokenStream<'source>>(
+		&self,
+		tokens: L,
+	) -> LimitedTokens<L> {
+		LimitedTokens::new(tokens, self.config.max_token_length)
+	}
+
+	/// Replaces the outcome of parsing `tokens` with an error about the
+	/// token that cut them off, if there was one, since the parser took the
+	/// input to end there. Errors recovered from are dropped along with the
+	/// rest, as they may be from the same confusion.
+	fn check_cut<'source, L: TokenStream<'source>, T>(
+		&mut self,
+		tokens: &LimitedTokens<L>,
+		result: Result<T>,
+	) -> Result<T> {
+		let Some(span) = tokens.cut() else {
+			return result;
+		};
+		if let Some(recovered) = &mut self.recovered {
+			recovered.clear();
+		}
+		Err(ParseError {
+			src: NamedSource::new(self.file_name, tokens.source().to_string()),
+			bad_bit: span.start.into(),
+			message: format!(
+				"token is {} bytes long, more than the limit of {}",
+				span.len(),
+				self.config.max_token_length
+			),
+			help: Some(
+				"raise `ParserConfig::max_token_length` if this is intended"
+					.to_owned(),
+			),
+		})?
+	}
+
+	/// Warns about each line whose indentation has a tab. Indentation is
+	/// found between tokens, so tabs inside strings aren't mistaken for it.
+	fn lint_tabs<'source, L: TokenStream<'source>>(&mut self, lexer: &L) {
+		let source = lexer.source();
+		let mut lexer = lexer.clone();
+		let mut gap_start = 0;
+		loop {
+			let next = lexer.next();
+			let gap_end = match next {
+				Some(_) => lexer.span().start,
+				None => source.len(),
+			};
+
+			let gap = &source[gap_start..gap_end];
+			let line_starts = (gap_start == 0)
+				.then_some(0)
+				.into_iter()
+				.chain(gap.match_indices('\n').map(|(i, _)| i + 1));
+			for start in line_starts {
+				let line = &gap[start..];
+				let indent =
+					line.len() - line.trim_start_matches([' ', '\t']).len();
+				let blank =
+					matches!(line[indent..].chars().next(), Some('\n' | '\r'))
+						|| (indent == line.len() && next.is_none());
+				if !blank && line[..indent].contains('\t') {
+					let start = gap_start + start;
+					self.warnings.push(
+						TabIndentation {
+							src: NamedSource::new(
+								self.file_name,
+								source.to_string(),
+							),
+							indent: (start..start + indent).into(),
+						}
+						.into(),
+					);
+				}
+			}
+
+			if next.is_none() {
+				return;
+			}
+			gap_start = lexer.span().end;
+		}
+	}
+
+	/// Parses a single value from `lexer`, leaving whatever comes after it.
+	pub fn parse_value<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+	) -> Result<Value<'source>> {
+		let mut tokens = self.limited(lexer.clone());
+		let result = self.parse_expr(&mut tokens);
+		let result = self.check_cut(&tokens, result);
+		*lexer = tokens.into_inner();
+		result
+	}
+
+	/// Parses a value nested in the one being parsed, from tokens that are
+	/// already limited in length.
+	fn parse_expr<'source, L: TokenStream<'source>>(
 		&mut self,
-		lexer: &mut Lexer<'source, Token<'source>>,
+		lexer: &mut L,
+	) -> Result<Value<'source>> {
+		self.validated(lexer, |parser, lexer| parser.parse_binary(lexer, 0))
+	}
+
+	/// Parses a value with `parse`, then runs the validator on it, if there
+	/// is one.
+	fn validated<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+		parse: impl FnOnce(&mut Self, &mut L) -> Result<Value<'source>>,
+	) -> Result<Value<'source>> {
+		if self.validator.is_none() {
+			return parse(self, lexer);
+		}
+
+		let mut lookahead = lexer.clone();
+		lookahead.next();
+		let start = lookahead.span().start;
+
+		let value = parse(self, lexer)?;
+		if let Some(validator) = &self.validator {
+			let span = SourceSpan::from(start..lexer.span().end);
+			if let Err(message) = validator(&value, span) {
+				Err(ParseError {
+					src: NamedSource::new(
+						self.file_name,
+						lexer.source().to_string(),
+					),
+					bad_bit: span,
+					message,
+					help: None,
+				})?;
+			}
+		}
+		Ok(value)
+	}
+
+	/// Parses operands joined by binary operators binding at least as
+	/// tightly as `min_precedence`, folding constant operands as it goes.
+	fn parse_binary<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+		min_precedence: u8,
+	) -> Result<Value<'source>> {
+		let mut lhs = self.parse_application(lexer)?;
+
+		while let Some((op, span, after)) = next_operator(lexer) {
+			if op.precedence() < min_precedence {
+				break;
+			}
+			*lexer = after;
+
+			let rhs_precedence = if op.is_right_associative() {
+				op.precedence()
+			} else {
+				op.precedence() + 1
+			};
+			let rhs = self.parse_binary(lexer, rhs_precedence)?;
+			lhs = eval::fold(op, lhs, rhs).map_err(|err| ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: span.clone().into(),
+				message: err.to_string(),
+				help: None,
+			})?;
+		}
+
+		Ok(lhs)
+	}
+
+	/// Parses an operand applied to any number of arguments, as in `f x y`,
+	/// which is `(f x) y`. Arguments are operands themselves, so `f a.b`
+	/// passes `a.b`.
+	///
+	/// Data such as `1` or `{ }` can't be called, so what follows it isn't
+	/// taken as an argument, leaving it to be reported as out of place.
+	fn parse_application<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+	) -> Result<Value<'source>> {
+		let mut function = self.parse_operand(lexer)?;
+		while is_callable(&function) && starts_argument(lexer) {
+			let arg = self.parse_operand(lexer)?;
+			function = Value::Apply(Box::new(function), Box::new(arg));
+		}
+		Ok(function)
+	}
+
+	/// Parses a primary value followed by any number of `.name` selections
+	/// and an optional `or` default. The chain is collected in a loop, so no
+	/// depth of selection can exhaust the stack.
+	fn parse_operand<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+	) -> Result<Value<'source>> {
+		let (value, mut path) = match self.integer_before_select(lexer) {
+			Some((value, name)) => (value, vec![name]),
+			None => (self.parse_primary(lexer)?, Vec::new()),
+		};
+
+		while lexer.clone().next() == Some(Ok(Token::Dot)) {
+			lexer.next();
+			match lexer.next() {
+				Some(Ok(Token::Identifier(name))) => path.push(name.into()),
+				Some(Ok(token)) if literal_name(&token).is_some() => {
+					path.push(literal_name(&token).unwrap().into())
+				}
+				_ => Err(ParseError {
+					src: NamedSource::new(
+						self.file_name,
+						lexer.source().to_string(),
+					),
+					bad_bit: lexer.span().into(),
+					message: "expected an attribute name (context: select)"
+						.to_owned(),
+					help: None,
+				})?,
+			}
+		}
+
+		// Catch `foo@bar` here rather than as a confusing error further on.
+		if lexer.clone().next() == Some(Ok(Token::At)) {
+			lexer.next();
+			Err(self.misplaced_at(lexer))?;
+		}
+
+		// And `x => y`, a function as other languages write it.
+		if lexer.clone().next() == Some(Ok(Token::FatArrow)) {
+			lexer.next();
+			Err(self.fat_arrow(lexer))?;
+		}
+
+		// Likewise `(6)/2`, since a `/` followed by a name starts a path.
+		let end = lexer.span().end;
+		let mut lookahead = lexer.clone();
+		if let Some(Ok(Token::Path(path))) = lookahead.next() {
+			if path.starts_with('/') && lookahead.span().start == end {
+				Err(ParseError {
+					src: NamedSource::new(
+						self.file_name,
+						lexer.source().to_string(),
+					),
+					bad_bit: lookahead.span().into(),
+					message: format!("unexpected path `{path}`"),
+					help: Some(
+						"division needs a space after the `/`, as in `6 / 2`"
+							.to_owned(),
+					),
+				})?;
+			}
+		}
+
+		if path.is_empty() {
+			return Ok(value);
+		}
+
+		// `or` is only a keyword right after a select, so it otherwise
+		// remains usable as a name.
+		let default =
+			if lexer.clone().next() == Some(Ok(Token::Identifier("or"))) {
+				lexer.next();
+				Some(Box::new(self.parse_operand(lexer)?))
+			} else {
+				None
+			};
+		Ok(Value::Select(Box::new(value), path, default))
+	}
+
+	/// Consumes an integer that is immediately selected from, such as
+	/// `1.foo`, returning the integer and the first name.
+	fn integer_before_select<'source, L: TokenStream<'source>>(
+		&self,
+		lexer: &mut L,
+	) -> Option<(Value<'source>, Cow<'source, str>)> {
+		let mut lookahead = lexer.clone();
+		let Some(Ok(Token::IntegerSelect(literal))) = lookahead.next() else {
+			return None;
+		};
+		let (digits, name) = literal.split_once('.')?;
+
+		let value = if self.config.raw_numbers {
+			Value::RawNumber(digits.into())
+		} else {
+			Value::Integer(digits.replace('_', "").parse().ok()?)
+		};
+		*lexer = lookahead;
+		Some((value, name.into()))
+	}
+
+	fn parse_primary<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
 	) -> Result<Value<'source>> {
 		let span = lexer.span();
 
 		if let Some(token) = lexer.next() {
 			match token {
+				Ok(Token::Minus) => {
+					let span = lexer.span();
+					let operand = self.parse_application(lexer)?;
+					Ok(eval::fold_negate(operand).map_err(|err| {
+						ParseError {
+							src: NamedSource::new(
+								self.file_name,
+								lexer.source().to_string(),
+							),
+							bad_bit: span.clone().into(),
+							message: err.to_string(),
+							help: None,
+						}
+					})?)
+				}
+				Ok(Token::ParenOpen) => {
+					let open = lexer.span();
+					if lexer.clone().next() == Some(Ok(Token::ParenClose)) {
+						lexer.next();
+						Err(ParseError {
+							src: NamedSource::new(
+								self.file_name,
+								lexer.source().to_string(),
+							),
+							bad_bit: (open.start..lexer.span().end).into(),
+							message: "empty parentheses are not a valid \
+							          expression"
+								.to_owned(),
+							help: Some(
+								"Nix has no unit value; use `null` for \
+								 nothing"
+									.to_owned(),
+							),
+						})?;
+					}
+
+					let value = self.parse_expr(lexer)?;
+					match lexer.next() {
+						Some(Ok(Token::ParenClose)) => Ok(value),
+						Some(Ok(
+							token @ (Token::BracketClose | Token::BraceClose),
+						)) => Err(self.mismatched_delimiter(lexer, open, token))?,
+						None => Err(ParseError {
+							src: NamedSource::new(
+								self.file_name,
+								lexer.source().to_string(),
+							),
+							bad_bit: open.into(),
+							message: "unmatched opening parenthesis".to_owned(),
+							help: None,
+						})?,
+						Some(_) => Err(ParseError {
+							src: NamedSource::new(
+								self.file_name,
+								lexer.source().to_string(),
+							),
+							bad_bit: lexer.span().into(),
+							message: "expected ')' (context: parentheses)"
+								.to_owned(),
+							help: None,
+						})?,
+					}
+				}
+				Ok(Token::Let) => self.parse_let_in(lexer),
+				Ok(Token::If) => {
+					let span = lexer.span();
+					let cond = self.parse_expr(lexer)?;
+					self.expect(
+						lexer,
+						Token::Then,
+						"expected 'then' (context: if)",
+					)?;
+					let then = self.parse_expr(lexer)?;
+					self.expect(
+						lexer,
+						Token::Else,
+						"expected 'else' (context: if)",
+					)?;
+					// Like the body of `with`, `else` extends as far as it can.
+					let otherwise = self.parse_expr(lexer)?;
+					Ok(eval::fold_if(cond, then, otherwise).map_err(|err| {
+						ParseError {
+							src: NamedSource::new(
+								self.file_name,
+								lexer.source().to_string(),
+							),
+							bad_bit: span.into(),
+							message: err.to_string(),
+							help: None,
+						}
+					})?)
+				}
+				Ok(Token::With) => {
+					let scope = self.parse_expr(lexer)?;
+					self.expect(
+						lexer,
+						Token::SemiColon,
+						"expected ';' (context: with)",
+					)?;
+					// The body extends as far to the right as possible.
+					let body = self.parse_expr(lexer)?;
+					Ok(Value::With(Box::new(scope), Box::new(body)))
+				}
+				// `rec` is only a keyword in front of an attrset, so it
+				// remains usable as an attribute name.
+				Ok(Token::Identifier("rec"))
+					if lexer.clone().next() == Some(Ok(Token::BraceOpen)) =>
+				{
+					lexer.next();
+					Ok(Value::RecAttrSet(
+						self.parse_bindings(lexer, Token::BraceClose)?,
+					))
+				}
+				Ok(
+					Token::Integer(_)
+					| Token::HexInteger(_)
+					| Token::OctalInteger(_)
+					| Token::BinaryInteger(_)
+					| Token::Float(_)
+					| Token::HexFloat(_),
+				) if self.config.raw_numbers => {
+					let span = lexer.span();
+					Ok(Value::RawNumber(lexer.source()[span].into()))
+				}
+				Ok(Token::Identifier(name))
+					if followed_by(lexer, &[Token::Colon]) =>
+				{
+					lexer.next();
+					let body = self.parse_expr(lexer)?;
+					Ok(Value::Lambda(
+						Pattern::Ident(name.into()),
+						Box::new(body),
+					))
+				}
+				Ok(Token::Identifier(name))
+					if followed_by(lexer, &[Token::At, Token::BraceOpen]) =>
+				{
+					lexer.next();
+					lexer.next();
+					self.parse_set_lambda(lexer, Some(name.into()))
+				}
+				Ok(Token::Identifier(name)) => Ok(Value::Ref(name.into())),
 				Ok(Token::Bool(b)) => Ok(Value::Bool(b)),
+				Ok(Token::Path(p)) => Ok(Value::Path(p.into())),
+				Ok(Token::String(literal)) => self.parse_string(lexer, literal),
+				Ok(Token::IndentedString(literal)) => Ok(Value::String(
+					self.decode_indented_string(lexer, literal)?,
+				)),
+				Ok(Token::BraceOpen) if starts_set_pattern(lexer) => {
+					self.parse_set_lambda(lexer, None)
+				}
 				Ok(Token::BraceOpen) => self.parse_attrset(lexer),
 				Ok(Token::BracketOpen) => self.parse_list(lexer),
 				Ok(Token::Null) => Ok(Value::Null),
 				Ok(Token::Float(n)) => Ok(Value::Float(n)),
-				Ok(Token::Integer(s)) => Ok(Value::Integer(s)),
+				Ok(
+					Token::Integer(n)
+					| Token::HexInteger(n)
+					| Token::OctalInteger(n)
+					| Token::BinaryInteger(n),
+				) => Ok(Value::Integer(n)),
+				Ok(Token::At) => Err(self.misplaced_at(lexer))?,
+				Ok(Token::InvalidNumber(literal))
+					if is_incomplete_exponent(literal) =>
+				{
+					Err(ParseError {
+						src: NamedSource::new(
+							self.file_name,
+							lexer.source().to_string(),
+						),
+						bad_bit: lexer.span().into(),
+						message: format!(
+							"incomplete exponent in number literal `{literal}`"
+						),
+						help: Some(
+							"an exponent needs digits, as in `1e3`".to_owned(),
+						),
+					})?
+				}
+				Ok(Token::InvalidNumber(literal)) => Err(ParseError {
+					src: NamedSource::new(
+						self.file_name,
+						lexer.source().to_string(),
+					),
+					bad_bit: lexer.span().into(),
+					message: format!("invalid number literal `{literal}`"),
+					help: Some(
+						"a number can't be directly followed by letters"
+							.to_owned(),
+					),
+				})?,
+				Ok(token) if misplaced_token(&token).is_some() => {
+					let (message, help) =
+						misplaced_token(&token).expect("matched a token");
+					Err(ParseError {
+						src: NamedSource::new(
+							self.file_name,
+							lexer.source().to_string(),
+						),
+						bad_bit: lexer.span().into(),
+						message: message.to_owned(),
+						help: Some(help.to_owned()),
+					})?
+				}
 				_ => Err(ParseError {
 					src: NamedSource::new(
 						self.file_name,
 						lexer.source().to_string(),
 					),
-					bad_bit: (span.start, span.end).into(),
+					bad_bit: lexer.span().into(),
 					message: "unexpected token (context: value)".to_owned(),
+					help: None,
 				})?,
 			}
 		} else {
@@ -70,123 +925,1086 @@ impl<'a> Parser<'a> {
 					self.file_name,
 					lexer.source().to_string(),
 				),
-				bad_bit: (span.start, span.end).into(),
+				bad_bit: span.clone().into(),
 				message: "empty values are not allowed".to_owned(),
+				help: None,
 			})?
 		}
 	}
 
-	pub fn parse_list<'source>(
+	pub fn parse_list<'source, L: TokenStream<'source>>(
 		&mut self,
-		lexer: &mut Lexer<'source, Token<'source>>,
+		lexer: &mut L,
 	) -> Result<Value<'source>> {
 		let mut array = Vec::new();
 		let span = lexer.span();
-		let mut awaits_space = false;
-		let mut awaits_value = false;
+		self.context.push(Context::List);
 
-		while let Some(token) = lexer.next() {
-			match token {
-				Ok(Token::Bool(b)) if !awaits_space => {
-					array.push(Value::Bool(b));
-					awaits_value = false;
-				}
-				Ok(Token::BraceOpen) if !awaits_space => {
-					let object = self.parse_attrset(lexer)?;
-					array.push(object);
-					awaits_value = false;
+		loop {
+			let mut lookahead = lexer.clone();
+			match lookahead.next() {
+				Some(Ok(Token::BracketClose)) => {
+					lexer.next();
+					self.context.pop();
+					return Ok(Value::List(array));
 				}
-				Ok(Token::BracketOpen) if !awaits_space => {
-					let sub_array = self.parse_list(lexer)?;
-					array.push(sub_array);
-					awaits_value = false;
+				Some(Ok(token @ (Token::BraceClose | Token::ParenClose))) => {
+					lexer.next();
+					Err(self.mismatched_delimiter(lexer, span.clone(), token))?
 				}
-				Ok(Token::BracketClose) if !awaits_value => {
-					return Ok(Value::List(array))
-				}
-				Ok(Token::Space) if awaits_space => awaits_value = true,
-				Ok(Token::Null) if !awaits_space => {
-					array.push(Value::Null);
-					awaits_value = false
-				}
-				Ok(Token::Float(n)) if !awaits_space => {
-					array.push(Value::Float(n));
-					awaits_value = false;
+				Some(Ok(Token::Identifier(_)))
+					if lookahead.next() == Some(Ok(Token::Equals)) =>
+				{
+					lexer.next();
+					Err(ParseError {
+						src: NamedSource::new(
+							self.file_name,
+							lexer.source().to_string(),
+						),
+						bad_bit: (lexer.span().start..lookahead.span().end)
+							.into(),
+						message: "unexpected attribute binding (context: list)"
+							.to_owned(),
+						help: Some(
+							"attribute sets are written with braces: `{ a = 1; }`"
+								.to_owned(),
+						),
+					})?
 				}
-				Ok(Token::Integer(s)) if !awaits_space => {
-					array.push(Value::Integer(s));
-					awaits_value = false;
+				Some(_) => {
+					array.push(self.validated(lexer, |parser, lexer| {
+						parser.parse_operand(lexer)
+					})?)
 				}
-				_ => Err(ParseError {
+				None => Err(ParseError {
 					src: NamedSource::new(
 						self.file_name,
 						lexer.source().to_string(),
 					),
-					bad_bit: (span.start, span.end).into(),
-					message: "unexpected token (context: list)".to_owned(),
+					bad_bit: span.clone().into(),
+					message:
+						"unmatched opening bracket defined (context: list)"
+							.to_owned(),
+					help: None,
 				})?,
 			}
-			awaits_space = !awaits_value;
 		}
-
-		Err(ParseError {
-			src: NamedSource::new(self.file_name, lexer.source().to_string()),
-			bad_bit: (span.start, span.end).into(),
-			message: "unmatched opening bracket defined (context: list)"
-				.to_owned(),
-		})?
 	}
 
-	pub fn parse_attrset<'source>(
+	/// Parses a `let` expression after its `let`.
+	pub fn parse_let_in<'source, L: TokenStream<'source>>(
 		&mut self,
-		lexer: &mut Lexer<'source, Token<'source>>,
+		lexer: &mut L,
 	) -> Result<Value<'source>> {
-		let mut map = HashMap::new();
 		let span = lexer.span();
-		let mut awaits_comma = false;
-		let mut awaits_key = false;
+		if lexer.clone().next() == Some(Ok(Token::In)) {
+			lexer.next();
+			Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: (span.start..lexer.span().end).into(),
+				message: "let expression requires at least one binding"
+					.to_owned(),
+				help: None,
+			})?;
+		}
 
-		while let Some(token) = lexer.next() {
-			match token {
-				Ok(Token::BraceClose) if !awaits_key => {
-					return Ok(Value::AttrSet(map))
+		let bindings = self.parse_bindings(lexer, Token::In)?;
+		let body = self.parse_expr(lexer)?;
+		Ok(Value::LetIn(bindings, Box::new(body)))
+	}
+
+	pub fn parse_attrset<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+	) -> Result<Value<'source>> {
+		Ok(Value::AttrSet(
+			self.parse_bindings(lexer, Token::BraceClose)?,
+		))
+	}
+
+	/// Parses a function taking apart an attrset, after the `{` of its
+	/// arguments. The whole argument may also be bound, by `name@` in front,
+	/// which the caller has already consumed as `bind`, or by `@name` after.
+	fn parse_set_lambda<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+		mut bind: Option<Cow<'source, str>>,
+	) -> Result<Value<'source>> {
+		let mut params = Vec::new();
+		let mut ellipsis = false;
+		loop {
+			match lexer.next() {
+				Some(Ok(Token::BraceClose)) => break,
+				Some(Ok(Token::Ellipsis)) => {
+					ellipsis = true;
+					self.expect(
+						lexer,
+						Token::BraceClose,
+						"expected '}' after '...' (context: function arguments)",
+					)?;
+					break;
 				}
-				Ok(Token::Comma) if awaits_comma => awaits_key = true,
-				Ok(Token::Identifier(key)) if !awaits_comma => {
+				Some(Ok(Token::Identifier(name))) => {
+					let default = if followed_by(lexer, &[Token::Question]) {
+						lexer.next();
+						Some(self.parse_expr(lexer)?)
+					} else {
+						None
+					};
+					params.push((name.into(), default));
+
 					match lexer.next() {
-						Some(Ok(Token::Equals)) => (),
+						Some(Ok(Token::Comma)) => (),
+						Some(Ok(Token::BraceClose)) => break,
 						_ => Err(ParseError {
 							src: NamedSource::new(
 								self.file_name,
 								lexer.source().to_string(),
 							),
-							bad_bit: (span.start, span.end).into(),
-							message: "expected '=' (context: attrset)"
+							bad_bit: lexer.span().into(),
+							message: "expected ',' or '}' (context: function \
+							          arguments)"
 								.to_owned(),
+							help: None,
 						})?,
 					}
-					let value = self.parse_value(lexer)?;
-					map.insert(key, value);
-					awaits_key = false;
 				}
-				_ => {
-					return Err(ParseError {
+				_ => Err(ParseError {
+					src: NamedSource::new(
+						self.file_name,
+						lexer.source().to_string(),
+					),
+					bad_bit: lexer.span().into(),
+					message: "expected an argument name (context: function \
+					          arguments)"
+						.to_owned(),
+					help: None,
+				})?,
+			}
+		}
+
+		if bind.is_none() && followed_by(lexer, &[Token::At]) {
+			lexer.next();
+			match lexer.next() {
+				Some(Ok(Token::Identifier(name))) => bind = Some(name.into()),
+				_ => Err(ParseError {
+					src: NamedSource::new(
+						self.file_name,
+						lexer.source().to_string(),
+					),
+					bad_bit: lexer.span().into(),
+					message: "expected a name after '@'".to_owned(),
+					help: None,
+				})?,
+			}
+		}
+		if lexer.clone().next() == Some(Ok(Token::FatArrow)) {
+			lexer.next();
+			Err(self.fat_arrow(lexer))?;
+		}
+		self.expect(
+			lexer,
+			Token::Colon,
+			"expected ':' after the arguments of a function",
+		)?;
+
+		let body = self.parse_expr(lexer)?;
+		Ok(Value::Lambda(
+			Pattern::Set {
+				params,
+				ellipsis,
+				bind,
+			},
+			Box::new(body),
+		))
+	}
+
+	/// Parses bindings up to and including `end`, which is the `}` of an
+	/// attrset or the `in` of a `let`.
+	fn parse_bindings<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+		end: Token<'source>,
+	) -> Result<Map<'source>> {
+		// Collected in order and hashed once at the end, so a large set is
+		// allocated once rather than rehashed as it grows.
+		let mut bindings = Vec::new();
+		let span = lexer.span();
+
+		let mut start = lexer.clone();
+		while let Some(token) = lexer.next() {
+			match token {
+				Ok(token) if token == end => {
+					return Ok(bindings.into_iter().collect());
+				}
+				Ok(Token::Inherit) => {
+					self.parse_inherit(lexer, &mut bindings)?
+				}
+				Ok(token @ (Token::BracketClose | Token::ParenClose))
+					if end == Token::BraceClose =>
+				{
+					Err(self.mismatched_delimiter(lexer, span.clone(), token))?
+				}
+				token => {
+					let depth = self.context.len();
+					match self.parse_binding(lexer, token, &end) {
+						Ok(binding) => bindings.push(binding),
+						Err(err) if self.recovered.is_some() => {
+							let err = self.with_breadcrumb(err);
+							self.context.truncate(depth);
+							self.recovered.as_mut().unwrap().push(err);
+							skip_binding(lexer, start.clone(), &end);
+						}
+						Err(err) => Err(err)?,
+					}
+				}
+			}
+			start = lexer.clone();
+		}
+
+		Err(ParseError {
+			src: NamedSource::new(self.file_name, lexer.source().to_string()),
+			bad_bit: span.clone().into(),
+			message: if end == Token::In {
+				"expected 'in' (context: let)".to_owned()
+			} else {
+				"unmatched opening brace (context: attrset)".to_owned()
+			},
+			help: None,
+		})?
+	}
+
+	/// Parses a single `name = value;` binding, starting from the token
+	/// already taken for its name.
+	fn parse_binding<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+		token: Result<Token<'source>, ()>,
+		end: &Token<'source>,
+	) -> Result<(Cow<'source, str>, Value<'source>)> {
+		let context = if *end == Token::In { "let" } else { "attrset" };
+		let start = lexer.span().start;
+		let key = match token {
+			Ok(Token::Identifier(key)) => Cow::Borrowed(key),
+			Ok(token) if literal_name(&token).is_some() => {
+				Cow::Borrowed(literal_name(&token).unwrap())
+			}
+			Ok(Token::String(literal)) => self.decode_string(lexer, literal)?,
+			Ok(Token::SemiColon) => Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: if *end == Token::In {
+					"unexpected ';' — expected an attribute name or 'in'"
+						.to_owned()
+				} else {
+					"unexpected ';' — expected an attribute name or '}'"
+						.to_owned()
+				},
+				help: None,
+			})?,
+			Ok(token) if token.keyword().is_some() => {
+				let keyword = token.keyword().expect("matched a keyword");
+				match self.config.keywords {
+					KeywordPolicy::AsNames => Cow::Borrowed(keyword),
+					KeywordPolicy::Reserved => Err(ParseError {
 						src: NamedSource::new(
 							self.file_name,
 							lexer.source().to_string(),
 						),
-						bad_bit: (span.start, span.end).into(),
-						message: "expected '=' (context: attrset)".to_owned(),
-					})?;
+						bad_bit: lexer.span().into(),
+						message: format!(
+							"`{keyword}` is a keyword, so it can't name an \
+							 attribute (context: {context})"
+						),
+						help: Some(format!(
+							"quote it to use it as a name: `\"{keyword}\" = ...;`"
+						)),
+					})?,
+				}
+			}
+			Ok(
+				Token::Integer(_)
+				| Token::HexInteger(_)
+				| Token::OctalInteger(_)
+				| Token::BinaryInteger(_)
+				| Token::Float(_)
+				| Token::Path(_)
+				| Token::BracketOpen
+				| Token::BraceOpen,
+			) => Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: format!(
+					"expected an attribute name, found a value (context: \
+					 {context})"
+				),
+				help: (*end == Token::BraceClose).then(|| {
+					"lists are written with brackets: `[ 1 2 ]`".to_owned()
+				}),
+			})?,
+			_ => Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: format!(
+					"expected an attribute name (context: {context})"
+				),
+				help: None,
+			})?,
+		};
+
+		self.expect(
+			lexer,
+			Token::Equals,
+			&format!("expected '=' (context: {context})"),
+		)?;
+		if lexer.clone().next().is_none() {
+			Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: "expected a value after '='".to_owned(),
+				help: None,
+			})?;
+		}
+		self.context.push(if *end == Token::In {
+			Context::LetBinding(key.to_string())
+		} else {
+			Context::Attribute(key.to_string())
+		});
+		let value = self.parse_expr(lexer)?;
+		self.context.pop();
+		if lexer.clone().next() == Some(Ok(Token::Comma)) {
+			lexer.next();
+			Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: "unexpected ',' — Nix uses ';' to separate attributes"
+					.to_owned(),
+				help: Some(
+					"end each binding with ';': `{ a = 1; b = 2; }`".to_owned(),
+				),
+			})?;
+		}
+		let closes = lexer.clone().next() == Some(Ok(end.clone()));
+		if !(self.config.lenient && closes) {
+			self.expect(
+				lexer,
+				Token::SemiColon,
+				&format!("expected ';' (context: {context})"),
+			)?;
+		}
+
+		if self.config.keep_comments && *end == Token::BraceClose {
+			self.keep_comments(lexer.source(), start..lexer.span().end, &key);
+		}
+		Ok((key, value))
+	}
+
+	/// Records the comments around the attribute `key`, written at `span`,
+	/// if it can be reached through attrsets.
+	fn keep_comments(&mut self, source: &str, span: Span, key: &str) {
+		let mut path = Vec::new();
+		for context in &self.context {
+			match context {
+				Context::Attribute(name) => path.push(name.clone()),
+				Context::List | Context::LetBinding(_) => return,
+			}
+		}
+		path.push(key.to_owned());
+
+		let comments = binding_comments(source, span);
+		if comments != Comments::default() {
+			self.comments.insert(path, comments);
+		}
+	}
+
+	/// Parses the names of an `inherit` binding, each of which binds to a
+	/// [`Value::Inherit`] of the same name in the enclosing scope.
+	fn parse_inherit<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+		bindings: &mut Vec<(Cow<'source, str>, Value<'source>)>,
+	) -> Result<()> {
+		loop {
+			match lexer.next() {
+				Some(Ok(Token::Identifier(name))) => {
+					bindings.push((name.into(), Value::Inherit(name.into())));
 				}
+				Some(Ok(Token::SemiColon)) => return Ok(()),
+				_ => Err(ParseError {
+					src: NamedSource::new(
+						self.file_name,
+						lexer.source().to_string(),
+					),
+					bad_bit: lexer.span().into(),
+					message: "expected a name or ';' (context: inherit)"
+						.to_owned(),
+					help: None,
+				})?,
 			}
-			awaits_comma = !awaits_key;
 		}
+	}
 
-		Err(ParseError {
+	/// Parses the string literal that was just consumed, which is a plain
+	/// string unless it interpolates values, as in `"a ${b} c"`. Those are
+	/// coerced to strings when the string is evaluated.
+	///
+	/// The literal is lexed as a single token, which ends at the first `"`,
+	/// so an interpolated value can't contain a string of its own.
+	fn parse_string<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &L,
+		literal: &'source str,
+	) -> Result<Value<'source>> {
+		let source = lexer.source();
+		let mut position = lexer.span().start + 1;
+		let end = lexer.span().end - 1;
+		if find_interpolation(&source[position..end]).is_none() {
+			let inner = &source[position..end];
+			return Ok(Value::String(
+				self.decode_escapes(lexer, position, inner)?,
+			));
+		}
+
+		let mut parts = Vec::new();
+		while let Some(offset) = find_interpolation(&source[position..end]) {
+			let text = &source[position..position + offset];
+			if !text.is_empty() {
+				parts.push(Value::String(
+					self.decode_escapes(lexer, position, text)?,
+				));
+			}
+
+			let mut expr = Token::lexer(source);
+			expr.bump(position + offset + 2);
+			parts.push(self.parse_value(&mut expr)?);
+			self.expect(
+				&mut expr,
+				Token::BraceClose,
+				"expected '}' (context: interpolation)",
+			)?;
+			if expr.span().end > end {
+				Err(ParseError {
+					src: NamedSource::new(self.file_name, source.to_string()),
+					bad_bit: (position + offset..end + 1).into(),
+					message: format!(
+						"unterminated interpolation in `{literal}`"
+					),
+					help: Some(
+						"strings inside an interpolation aren't supported"
+							.to_owned(),
+					),
+				})?;
+			}
+			position = expr.span().end;
+		}
+		if position < end {
+			let text = &source[position..end];
+			parts.push(Value::String(
+				self.decode_escapes(lexer, position, text)?,
+			));
+		}
+
+		Ok(Value::Interpolate(parts))
+	}
+
+	/// Decodes the escapes of the string literal that was just consumed,
+	/// borrowing it if it has none. It names an attribute, so it can't
+	/// interpolate.
+	fn decode_string<'source, L: TokenStream<'source>>(
+		&self,
+		lexer: &L,
+		literal: &'source str,
+	) -> Result<Cow<'source, str>> {
+		let start = lexer.span().start + 1;
+		let inner = &literal[1..literal.len() - 1];
+		if let Some(offset) = find_interpolation(inner) {
+			Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: (start + offset..start + offset + 2).into(),
+				message: "interpolated attribute names aren't supported"
+					.to_owned(),
+				help: None,
+			})?;
+		}
+		self.decode_escapes(lexer, start, inner)
+	}
+
+	/// Decodes the escapes of `inner`, part of a string literal starting at
+	/// `start` in the source, borrowing it if it has none.
+	fn decode_escapes<'source, L: TokenStream<'source>>(
+		&self,
+		lexer: &L,
+		start: usize,
+		inner: &'source str,
+	) -> Result<Cow<'source, str>> {
+		let error = |offset: usize, len: usize, message: String| ParseError {
 			src: NamedSource::new(self.file_name, lexer.source().to_string()),
-			bad_bit: (span.start, span.end).into(),
-			message: "unmatched opening brace (context: attrset)".to_owned(),
-		})?
+			bad_bit: (start + offset..start + offset + len).into(),
+			message,
+			help: None,
+		};
+
+		if !inner.contains('\\') {
+			return Ok(Cow::Borrowed(inner));
+		}
+
+		let mut decoded = String::with_capacity(inner.len());
+		let mut chars = inner.char_indices();
+		while let Some((offset, c)) = chars.next() {
+			if c != '\\' {
+				decoded.push(c);
+				continue;
+			}
+
+			// The lexer doesn't end a string on a backslash.
+			let (_, escaped) = chars.next().expect("escape after a backslash");
+			match escaped {
+				'n' => decoded.push('\n'),
+				'r' => decoded.push('\r'),
+				't' => decoded.push('\t'),
+				'\\' | '"' | '$' => decoded.push(escaped),
+				_ if self.config.escapes == StringEscapePolicy::Passthrough => {
+					decoded.push('\\');
+					decoded.push(escaped);
+				}
+				_ => Err(error(
+					offset,
+					1 + escaped.len_utf8(),
+					format!("unknown escape sequence `\\{escaped}`"),
+				))?,
+			}
+		}
+
+		Ok(Cow::Owned(decoded))
+	}
+
+	/// Decodes the indented string literal that was just consumed, stripping
+	/// its indentation before decoding its escapes: `'''` for `''`, `''$`
+	/// for `$`, so that `''${` is a literal `${`, and `''\n` and the like.
+	fn decode_indented_string<'source, L: TokenStream<'source>>(
+		&self,
+		lexer: &L,
+		literal: &'source str,
+	) -> Result<Cow<'source, str>> {
+		let inner = &literal[2..literal.len() - 2];
+		if let Some(offset) = find_indented_interpolation(inner) {
+			let start = lexer.span().start + 2 + offset;
+			Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: (start..start + 2).into(),
+				message: "interpolation in indented strings isn't supported"
+					.to_owned(),
+				help: None,
+			})?;
+		}
+
+		let stripped = strip_indentation(inner);
+		let mut decoded = String::with_capacity(stripped.len());
+		let mut rest = stripped.as_str();
+		while let Some(c) = rest.chars().next() {
+			if let Some(after) = rest.strip_prefix("'''") {
+				decoded.push_str("''");
+				rest = after;
+			} else if let Some(after) = rest.strip_prefix("''$") {
+				decoded.push('$');
+				rest = after;
+			} else if let Some(after) = rest.strip_prefix("''\\") {
+				let mut chars = after.chars();
+				// The lexer doesn't end a string on an escape.
+				match chars.next().expect("escape after `''\\`") {
+					'n' => decoded.push('\n'),
+					'r' => decoded.push('\r'),
+					't' => decoded.push('\t'),
+					escaped => decoded.push(escaped),
+				}
+				rest = chars.as_str();
+			} else {
+				decoded.push(c);
+				rest = &rest[c.len_utf8()..];
+			}
+		}
+
+		Ok(Cow::Owned(decoded))
+	}
+
+	/// An error for the closing `found`, just consumed, where the delimiter
+	/// opened at `open` should have been closed.
+	fn mismatched_delimiter<'source, L: TokenStream<'source>>(
+		&self,
+		lexer: &L,
+		open: Span,
+		found: Token<'source>,
+	) -> MismatchedDelimiter {
+		let source = lexer.source();
+		let open_char = source[open.clone()].chars().next().unwrap_or('(');
+		MismatchedDelimiter {
+			src: NamedSource::new(self.file_name, source.to_string()),
+			open_span: open.into(),
+			found_span: lexer.span().into(),
+			open: open_char,
+			expected: match open_char {
+				'[' => ']',
+				'{' => '}',
+				_ => ')',
+			},
+			found: match found {
+				Token::BracketClose => ']',
+				Token::BraceClose => '}',
+				_ => ')',
+			},
+		}
+	}
+
+	/// The error for an `@` that was just consumed, which Nix only allows
+	/// in function arguments.
+	fn misplaced_at<'source, L: TokenStream<'source>>(
+		&self,
+		lexer: &L,
+	) -> ParseError {
+		ParseError {
+			src: NamedSource::new(self.file_name, lexer.source().to_string()),
+			bad_bit: lexer.span().into(),
+			message: "unexpected '@'".to_owned(),
+			help: Some(
+				"'@' can only bind the argument of a function, as in \
+				 `args@{ a, ... }: a`"
+					.to_owned(),
+			),
+		}
+	}
+
+	/// The error for a `=>` that was just consumed, where a function was
+	/// likely meant.
+	fn fat_arrow<'source, L: TokenStream<'source>>(
+		&self,
+		lexer: &L,
+	) -> ParseError {
+		ParseError {
+			src: NamedSource::new(self.file_name, lexer.source().to_string()),
+			bad_bit: lexer.span().into(),
+			message: "Nix lambdas use ':' not '=>'".to_owned(),
+			help: Some("write the function as `x: y`".to_owned()),
+		}
 	}
+
+	/// Consumes the next token, failing with `message` unless it is
+	/// `expected`.
+	fn expect<'source, L: TokenStream<'source>>(
+		&mut self,
+		lexer: &mut L,
+		expected: Token<'source>,
+		message: &str,
+	) -> Result<()> {
+		match lexer.next() {
+			Some(Ok(token)) if token == expected => Ok(()),
+			_ => Err(ParseError {
+				src: NamedSource::new(
+					self.file_name,
+					lexer.source().to_string(),
+				),
+				bad_bit: lexer.span().into(),
+				message: message.to_owned(),
+				help: None,
+			})?,
+		}
+	}
+}
+
+/// The bindings of an attrset, parsed as they are iterated over. Created by
+/// [`Parser::attr_stream`].
+pub struct AttrStream<'p, 'a, 'source, L> {
+	parser: &'p mut Parser<'a>,
+	lexer: LimitedTokens<L>,
+	/// The span of the `{`, once it has been consumed.
+	opening: Option<Span>,
+	/// Bindings of an `inherit` that are still to be yielded.
+	pending: Vec<(Cow<'source, str>, Value<'source>)>,
+	done: bool,
+}
+
+impl<'source, L: TokenStream<'source>> AttrStream<'_, '_, 'source, L> {
+	fn advance(
+		&mut self,
+	) -> Result<Option<(Cow<'source, str>, Value<'source>)>> {
+		let lexer = &mut self.lexer;
+		let opening = match &self.opening {
+			Some(opening) => opening.clone(),
+			None => {
+				self.parser.expect(
+					lexer,
+					Token::BraceOpen,
+					"expected '{' (context: attrset)",
+				)?;
+				self.opening.insert(lexer.span()).clone()
+			}
+		};
+
+		loop {
+			match lexer.next() {
+				Some(Ok(Token::BraceClose)) => return Ok(None),
+				Some(Ok(Token::Inherit)) => {
+					let mut names = Vec::new();
+					self.parser.parse_inherit(lexer, &mut names)?;
+					// Popped from the end, so reversed to come out in order.
+					self.pending.extend(names.into_iter().rev());
+					if let Some(binding) = self.pending.pop() {
+						return Ok(Some(binding));
+					}
+				}
+				Some(token) => {
+					return self
+						.parser
+						.parse_binding(lexer, token, &Token::BraceClose)
+						.map(Some);
+				}
+				None => Err(ParseError {
+					src: NamedSource::new(
+						self.parser.file_name,
+						lexer.source().to_string(),
+					),
+					bad_bit: opening.clone().into(),
+					message: "unmatched opening brace (context: attrset)"
+						.to_owned(),
+					help: None,
+				})?,
+			}
+		}
+	}
+}
+
+impl<'source, L: TokenStream<'source>> Iterator
+	for AttrStream<'_, '_, 'source, L>
+{
+	type Item = Result<(Cow<'source, str>, Value<'source>)>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if let Some(binding) = self.pending.pop() {
+			return Some(Ok(binding));
+		}
+		if self.done {
+			return None;
+		}
+
+		// Only an error can be from reaching a token that's too long; a
+		// binding parsed before it stands.
+		let binding = self
+			.advance()
+			.map_err(|err| self.parser.attach_context(err))
+			.or_else(|err| self.parser.check_cut(&self.lexer, Err(err)))
+			.transpose();
+		// Nothing follows the closing brace or an error.
+		self.done = !matches!(binding, Some(Ok(_)));
+		binding
+	}
+}
+
+/// Moves `lexer` past a binding that failed to parse, through its `;`, or
+/// up to the `end` of the enclosing bindings. The binding's tokens are gone
+/// through again from its `start`, so that the brackets the error was
+/// nested in are skipped as a whole, and so that a `;` or `end` the parser
+/// already took isn't skipped past. A `let` is nested up to its `in`, and a
+/// `with` up to its `;`.
+fn skip_binding<'source, L: TokenStream<'source>>(
+	lexer: &mut L,
+	start: L,
+	end: &Token<'source>,
+) {
+	let mut nesting = Vec::new();
+	*lexer = start;
+	loop {
+		let mut lookahead = lexer.clone();
+		let Some(token) = lookahead.next() else {
+			return;
+		};
+		match token {
+			Ok(token) if nesting.is_empty() && token == *end => return,
+			Ok(Token::SemiColon) if nesting.is_empty() => {
+				*lexer = lookahead;
+				return;
+			}
+			Ok(Token::SemiColon) if nesting.last() == Some(&Token::With) => {
+				nesting.pop();
+			}
+			Ok(Token::In) if nesting.last() == Some(&Token::Let) => {
+				nesting.pop();
+			}
+			Ok(
+				token @ (Token::BraceOpen
+				| Token::BracketOpen
+				| Token::ParenOpen
+				| Token::Let
+				| Token::With),
+			) => nesting.push(token),
+			Ok(Token::BraceClose | Token::BracketClose | Token::ParenClose) => {
+				nesting.pop();
+			}
+			_ => (),
+		}
+		*lexer = lookahead;
+	}
+}
+
+/// Finds the comments around a binding written at `span`: the comment
+/// lines directly above it, if it starts its line, and a comment following
+/// it on the same line.
+fn binding_comments(source: &str, span: Span) -> Comments {
+	let comment = |line: &str| {
+		line.trim()
+			.strip_prefix('#')
+			.map(|text| text.trim().to_owned())
+	};
+
+	let before = &source[..span.start];
+	let (above, indent) = before.rsplit_once('\n').unwrap_or(("", before));
+	let mut leading = Vec::new();
+	let mut blank_line_before = false;
+	if indent.trim().is_empty() {
+		// Split rather than `lines`, which would drop a blank last line.
+		let lines = above.split('\n').rev();
+		leading = lines.clone().map_while(comment).collect();
+		blank_line_before = lines
+			.clone()
+			.nth(leading.len())
+			.is_some_and(|line| line.trim().is_empty());
+		leading.reverse();
+	}
+
+	let after = &source[span.end..];
+	let rest = after.split('\n').next().unwrap_or("");
+	Comments {
+		leading,
+		trailing: comment(rest),
+		blank_line_before,
+	}
+}
+
+/// Whether a value might be a function. Only data, which never is, is
+/// ruled out; an attrset is callable if it has a `__functor`.
+fn is_callable(value: &Value) -> bool {
+	match value {
+		Value::List(_) => false,
+		Value::AttrSet(map) | Value::RecAttrSet(map) => {
+			map.contains_key("__functor")
+		}
+		value => !value.is_scalar(),
+	}
+}
+
+/// Whether the next token starts an operand, which makes it an argument
+/// when it follows another.
+fn starts_argument<'source, L: TokenStream<'source>>(lexer: &L) -> bool {
+	matches!(
+		lexer.clone().next(),
+		Some(Ok(Token::Identifier(name))) if name != "or"
+	) || matches!(
+		lexer.clone().next(),
+		Some(Ok(Token::Integer(_)
+			| Token::HexInteger(_)
+			| Token::OctalInteger(_)
+			| Token::BinaryInteger(_)
+			| Token::Float(_)
+			| Token::HexFloat(_)
+			| Token::IntegerSelect(_)
+			| Token::Bool(_)
+			| Token::Null
+			| Token::String(_)
+			| Token::IndentedString(_)
+			| Token::Path(_)
+			| Token::ParenOpen
+			| Token::BracketOpen
+			| Token::BraceOpen))
+	)
+}
+
+/// The name spelled by a `true`, `false` or `null` token. Nix only makes
+/// these names in scope, not keywords, so they can name attributes too, as
+/// in `{ true = 1; }` or `x.null`.
+fn literal_name(token: &Token) -> Option<&'static str> {
+	match token {
+		Token::Bool(true) => Some("true"),
+		Token::Bool(false) => Some("false"),
+		Token::Null => Some("null"),
+		_ => None,
+	}
+}
+
+/// Whether the next tokens are `tokens`, without consuming them.
+fn followed_by<'source, L: TokenStream<'source>>(
+	lexer: &L,
+	tokens: &[Token<'source>],
+) -> bool {
+	let mut lookahead = lexer.clone();
+	tokens
+		.iter()
+		.all(|token| lookahead.next() == Some(Ok(token.clone())))
+}
+
+/// Whether the `{` just consumed opens the arguments of a function rather
+/// than an attrset. Both start out alike, so this looks past the first name
+/// to tell `{ a, b }: a` or `{ a ? 1 }: a` from `{ a = 1; }`.
+fn starts_set_pattern<'source, L: TokenStream<'source>>(lexer: &L) -> bool {
+	let mut lookahead = lexer.clone();
+	let closes_pattern = |mut lookahead: L| {
+		matches!(
+			lookahead.next(),
+			Some(Ok(Token::Colon | Token::At | Token::FatArrow))
+		)
+	};
+	match lookahead.next() {
+		Some(Ok(Token::Ellipsis)) => true,
+		Some(Ok(Token::BraceClose)) => closes_pattern(lookahead),
+		Some(Ok(Token::Identifier(_))) => match lookahead.next() {
+			Some(Ok(Token::Comma | Token::Question)) => true,
+			Some(Ok(Token::BraceClose)) => closes_pattern(lookahead),
+			_ => false,
+		},
+		_ => false,
+	}
+}
+
+/// The error message and help for a token found where a value should start
+/// that only has meaning elsewhere, such as the `=` of a binding.
+fn misplaced_token(token: &Token) -> Option<(&'static str, &'static str)> {
+	Some(match token {
+		Token::Equals => (
+			"unexpected '='; did you forget an attribute name?",
+			"bindings are written `name = value;`, inside `{ }` or after `let`",
+		),
+		Token::SemiColon => (
+			"unexpected ';'; did you forget a value?",
+			"a `;` ends a binding, as in `a = 1;`",
+		),
+		Token::Colon => (
+			"unexpected ':'; did you forget the argument of a function?",
+			"functions are written `x: body`",
+		),
+		Token::Comma => (
+			"unexpected ','",
+			"list elements are separated by spaces, as in `[ 1 2 ]`",
+		),
+		Token::In => (
+			"unexpected 'in' without a 'let'",
+			"bindings are written `let a = 1; in a`",
+		),
+		Token::Then => (
+			"unexpected 'then' without an 'if'",
+			"conditionals are written `if c then a else b`",
+		),
+		Token::Else => (
+			"unexpected 'else' without an 'if'",
+			"conditionals are written `if c then a else b`",
+		),
+		_ => return None,
+	})
+}
+
+/// Whether an invalid number literal is a number with an exponent marker but
+/// no exponent, such as `1e` or `1.5e-`.
+fn is_incomplete_exponent(literal: &str) -> bool {
+	literal
+		.trim_end_matches(['+', '-'])
+		.strip_suffix(['e', 'E'])
+		.is_some_and(|mantissa| {
+			mantissa
+				.chars()
+				.all(|c| c.is_ascii_digit() || "_.".contains(c))
+		})
+}
+
+/// The offset of the first unescaped `${` in the inside of a string.
+fn find_interpolation(inner: &str) -> Option<usize> {
+	let bytes = inner.as_bytes();
+	let mut i = 0;
+	while i + 1 < bytes.len() {
+		match &bytes[i..i + 2] {
+			[b'\\', _] => i += 2,
+			b"${" => return Some(i),
+			_ => i += 1,
+		}
+	}
+	None
+}
+
+/// The offset of the first unescaped `${` in the inside of an indented
+/// string, where `''${` is a literal `${`.
+fn find_indented_interpolation(inner: &str) -> Option<usize> {
+	let bytes = inner.as_bytes();
+	let mut i = 0;
+	while i + 1 < bytes.len() {
+		let rest = &bytes[i..];
+		if rest.starts_with(b"${") {
+			return Some(i);
+		}
+		let escaped = [&b"'''"[..], b"''$", b"''\\"]
+			.iter()
+			.any(|escape| rest.starts_with(escape));
+		i += if escaped { 3 } else { 1 };
+	}
+	None
+}
+
+/// Removes the indentation common to the lines of an indented string, and
+/// its first line if that's blank, as Nix does. Only spaces count as
+/// indentation, and lines of nothing but spaces don't count at all.
+fn strip_indentation(inner: &str) -> String {
+	let mut lines = inner.split('\n').collect::<Vec<_>>();
+	if lines.len() > 1 && lines[0].trim().is_empty() {
+		lines.remove(0);
+	}
+
+	let indent = lines
+		.iter()
+		.filter(|line| !line.trim_start_matches(' ').is_empty())
+		.map(|line| line.len() - line.trim_start_matches(' ').len())
+		.min()
+		.unwrap_or(0);
+	lines
+		.iter()
+		.map(|line| line.get(indent..).unwrap_or(""))
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+/// Looks ahead for a binary operator. On a match, returns the operator, its
+/// span and a lexer positioned after it.
+fn next_operator<'source, L: TokenStream<'source>>(
+	lexer: &L,
+) -> Option<(BinOp, Span, L)> {
+	let mut lookahead = lexer.clone();
+	let op = match lookahead.next()? {
+		Ok(Token::Plus) => BinOp::Add,
+		Ok(Token::Minus) => BinOp::Sub,
+		Ok(Token::Star) => BinOp::Mul,
+		Ok(Token::Slash) => BinOp::Div,
+		Ok(Token::Concat) => BinOp::Concat,
+		Ok(Token::EqEq) => BinOp::Eq,
+		Ok(Token::Update) => BinOp::Update,
+		_ => return None,
+	};
+
+	Some((op, lookahead.span(), lookahead))
 }
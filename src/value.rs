@@ -0,0 +1,1501 @@
+use crate::eval::{self, BinOp};
+use miette::{Diagnostic, Result};
+use std::{
+	borrow::Cow,
+	collections::{hash_map::Entry, HashMap},
+	path::{Component, Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum ValueError {
+	#[error("expected {expected}, got {got}")]
+	#[diagnostic(code(nixc::value::type_mismatch), url(docsrs))]
+	TypeMismatch { expected: String, got: &'static str },
+
+	#[error("cannot coerce {0} to a string")]
+	#[diagnostic(code(nixc::value::not_coercible), url(docsrs))]
+	NotCoercible(&'static str),
+
+	#[error("cannot export `{name}`, a {got}, as an environment variable")]
+	#[diagnostic(code(nixc::value::not_exportable), url(docsrs))]
+	NotExportable { name: String, got: &'static str },
+
+	#[error("attribute `{0}` is missing")]
+	#[diagnostic(code(nixc::value::missing_attribute), url(docsrs))]
+	MissingAttribute(String),
+
+	#[error("renaming both `{first}` and `{second}` gives `{renamed}`")]
+	#[diagnostic(code(nixc::value::key_collision), url(docsrs))]
+	KeyCollision {
+		first: String,
+		second: String,
+		renamed: String,
+	},
+
+	#[error("cannot write `{name}`, a {got}, as a key-value line")]
+	#[diagnostic(code(nixc::value::not_flat), url(docsrs))]
+	NotFlat { name: String, got: &'static str },
+
+	#[error("cannot flatten `{path}`, a {got}, into a single key")]
+	#[diagnostic(code(nixc::value::not_scalar), url(docsrs))]
+	NotScalar { path: String, got: &'static str },
+
+	#[error("`{first}` and `{second}` both flatten to the key `{key}`")]
+	#[diagnostic(code(nixc::value::flat_key_collision), url(docsrs))]
+	FlatKeyCollision {
+		first: String,
+		second: String,
+		key: String,
+	},
+
+	#[error("cannot zip lists of different lengths, {left} and {right}")]
+	#[diagnostic(code(nixc::value::length_mismatch), url(docsrs))]
+	LengthMismatch { left: usize, right: usize },
+}
+
+/// The bindings of an attrset or `let`, by name.
+pub type Map<'source> = HashMap<Cow<'source, str>, Value<'source>>;
+
+/// A value that doesn't borrow from its source.
+pub type OwnedValue = Value<'static>;
+
+/// Represent any valid JSON value.
+#[derive(Debug, Clone)]
+pub enum Value<'source> {
+	Null,
+	Bool(bool),
+	Integer(i64),
+	Float(f64),
+	/// A number literal exactly as written, such as `0xFF`, kept in place of
+	/// its value when [`ParserConfig::raw_numbers`] is set.
+	///
+	/// [`ParserConfig::raw_numbers`]: crate::parser::ParserConfig::raw_numbers
+	RawNumber(Cow<'source, str>),
+	String(Cow<'source, str>),
+	/// A path as written, such as `./x` or `/etc/x`.
+	Path(Cow<'source, str>),
+	List(Vec<Value<'source>>),
+	AttrSet(Map<'source>),
+	/// `rec { ... }`, whose bindings can refer to each other.
+	RecAttrSet(Map<'source>),
+	LetIn(Map<'source>, Box<Value<'source>>),
+	/// A reference to a variable in scope.
+	Ref(Cow<'source, str>),
+	/// The value of a binding made by `inherit x`: the `x` of the scope
+	/// around the one it's bound in, rather than the binding itself.
+	Inherit(Cow<'source, str>),
+	/// `with scope; body`, bringing the attributes of `scope` into scope.
+	With(Box<Value<'source>>, Box<Value<'source>>),
+	/// `if cond then a else b` with a condition that couldn't be folded
+	/// while parsing.
+	If(
+		Box<Value<'source>>,
+		Box<Value<'source>>,
+		Box<Value<'source>>,
+	),
+	/// `value.a.b`, selecting a path of attributes, or `value.a.b or default`
+	/// with a default for when the path doesn't exist.
+	Select(
+		Box<Value<'source>>,
+		Vec<Cow<'source, str>>,
+		Option<Box<Value<'source>>>,
+	),
+	/// An operation whose operands couldn't be folded while parsing.
+	BinOp(BinOp, Box<Value<'source>>, Box<Value<'source>>),
+	/// A function, such as `x: x + 1` or `{ a, b ? 2 }: a + b`.
+	Lambda(Pattern<'source>, Box<Value<'source>>),
+	/// `f x`, applying a function to an argument.
+	Apply(Box<Value<'source>>, Box<Value<'source>>),
+	/// `"a ${b} c"`, the literal parts of a string and the values
+	/// interpolated between them, in order.
+	Interpolate(Vec<Value<'source>>),
+}
+
+/// What a function binds its argument to.
+#[derive(Debug, Clone)]
+pub enum Pattern<'source> {
+	/// `x: ...`, binding the whole argument to a name.
+	Ident(Cow<'source, str>),
+	/// `{ a, b ? default, ... }: ...`, taking apart an attrset argument,
+	/// optionally also bound as a whole, as in `args@{ a }: ...`.
+	Set {
+		params: Vec<(Cow<'source, str>, Option<Value<'source>>)>,
+		ellipsis: bool,
+		bind: Option<Cow<'source, str>>,
+	},
+}
+
+impl<'source> Pattern<'source> {
+	/// Copies everything still borrowed from the source, like
+	/// [`Value::into_owned`].
+	pub fn into_owned(self) -> Pattern<'static> {
+		let owned = |name: Cow<'source, str>| Cow::Owned(name.into_owned());
+		match self {
+			Pattern::Ident(name) => Pattern::Ident(owned(name)),
+			Pattern::Set {
+				params,
+				ellipsis,
+				bind,
+			} => Pattern::Set {
+				params: params
+					.into_iter()
+					.map(|(name, default)| {
+						(owned(name), default.map(Value::into_owned))
+					})
+					.collect(),
+				ellipsis,
+				bind: bind.map(owned),
+			},
+		}
+	}
+}
+
+impl<'source> Value<'source> {
+	/// The name of the value's type, as reported by `builtins.typeOf`.
+	/// Expressions that still need evaluating are reported as `thunk`.
+	pub fn type_name(&self) -> &'static str {
+		match self {
+			Value::Null => "null",
+			Value::Bool(_) => "bool",
+			Value::Integer(_) => "int",
+			Value::Float(_) => "float",
+			Value::RawNumber(_) => "number",
+			Value::String(_) => "string",
+			Value::Path(_) => "path",
+			Value::List(_) => "list",
+			Value::AttrSet(_) | Value::RecAttrSet(_) => "set",
+			Value::LetIn(..)
+			| Value::Ref(_)
+			| Value::Inherit(_)
+			| Value::With(..)
+			| Value::If(..)
+			| Value::Select(..)
+			| Value::BinOp(..)
+			| Value::Apply(..)
+			| Value::Interpolate(_) => "thunk",
+			Value::Lambda(..) => "lambda",
+		}
+	}
+
+	/// The value itself if its [`type_name`](Self::type_name) is `expected`,
+	/// so checks can be chained, and a type mismatch otherwise.
+	pub fn ensure_type(&self, expected: &str) -> Result<&Self> {
+		let got = self.type_name();
+		if got != expected {
+			Err(ValueError::TypeMismatch {
+				expected: expected.to_owned(),
+				got,
+			})?;
+		}
+		Ok(self)
+	}
+
+	/// The bindings of an attrset, or a type mismatch for anything else.
+	/// Values carry no spans, so the error doesn't point into the source.
+	pub fn expect_attrset(&self) -> Result<&Map<'source>> {
+		match self {
+			Value::AttrSet(map) | Value::RecAttrSet(map) => Ok(map),
+			_ => Err(ValueError::TypeMismatch {
+				expected: "set".into(),
+				got: self.type_name(),
+			})?,
+		}
+	}
+
+	/// The elements of a list, or a type mismatch for anything else, like
+	/// [`expect_attrset`](Self::expect_attrset).
+	pub fn expect_list(&self) -> Result<&[Value<'source>]> {
+		match self {
+			Value::List(items) => Ok(items),
+			_ => Err(ValueError::TypeMismatch {
+				expected: "list".into(),
+				got: self.type_name(),
+			})?,
+		}
+	}
+
+	/// Copies everything still borrowed from the source, so the value can
+	/// outlive it.
+	pub fn into_owned(self) -> OwnedValue {
+		let owned_map = |map: Map<'source>| -> Map<'static> {
+			map.into_iter()
+				.map(|(key, value)| {
+					(Cow::Owned(key.into_owned()), value.into_owned())
+				})
+				.collect()
+		};
+
+		match self {
+			Value::Null => Value::Null,
+			Value::Bool(b) => Value::Bool(b),
+			Value::Integer(n) => Value::Integer(n),
+			Value::Float(n) => Value::Float(n),
+			Value::RawNumber(s) => Value::RawNumber(Cow::Owned(s.into_owned())),
+			Value::String(s) => Value::String(Cow::Owned(s.into_owned())),
+			Value::Path(p) => Value::Path(Cow::Owned(p.into_owned())),
+			Value::List(items) => {
+				Value::List(items.into_iter().map(Value::into_owned).collect())
+			}
+			Value::AttrSet(map) => Value::AttrSet(owned_map(map)),
+			Value::RecAttrSet(map) => Value::RecAttrSet(owned_map(map)),
+			Value::LetIn(bindings, body) => {
+				Value::LetIn(owned_map(bindings), Box::new(body.into_owned()))
+			}
+			Value::Ref(name) => Value::Ref(Cow::Owned(name.into_owned())),
+			Value::Inherit(name) => {
+				Value::Inherit(Cow::Owned(name.into_owned()))
+			}
+			Value::With(scope, body) => Value::With(
+				Box::new(scope.into_owned()),
+				Box::new(body.into_owned()),
+			),
+			Value::If(cond, then, otherwise) => Value::If(
+				Box::new(cond.into_owned()),
+				Box::new(then.into_owned()),
+				Box::new(otherwise.into_owned()),
+			),
+			Value::Select(value, path, default) => Value::Select(
+				Box::new(value.into_owned()),
+				path.into_iter()
+					.map(|name| Cow::Owned(name.into_owned()))
+					.collect(),
+				default.map(|default| Box::new(default.into_owned())),
+			),
+			Value::BinOp(op, lhs, rhs) => Value::BinOp(
+				op,
+				Box::new(lhs.into_owned()),
+				Box::new(rhs.into_owned()),
+			),
+			Value::Lambda(pattern, body) => {
+				Value::Lambda(pattern.into_owned(), Box::new(body.into_owned()))
+			}
+			Value::Apply(function, arg) => Value::Apply(
+				Box::new(function.into_owned()),
+				Box::new(arg.into_owned()),
+			),
+			Value::Interpolate(parts) => Value::Interpolate(
+				parts.into_iter().map(Value::into_owned).collect(),
+			),
+		}
+	}
+
+	/// Like [`into_owned`](Self::into_owned), but copies the value rather
+	/// than consuming it, so the borrowed tree can still be used.
+	pub fn to_owned_value(&self) -> OwnedValue {
+		self.clone().into_owned()
+	}
+
+	/// Concatenates several lists into one, like chaining `++`.
+	pub fn concat_lists(lists: &[Value<'source>]) -> Result<Value<'source>> {
+		let mut result = Vec::new();
+		for list in lists {
+			match list {
+				Value::List(items) => result.extend(items.iter().cloned()),
+				_ => Err(ValueError::TypeMismatch {
+					expected: "list".into(),
+					got: list.type_name(),
+				})?,
+			}
+		}
+
+		Ok(Value::List(result))
+	}
+
+	/// Pairs up the elements of two lists into `{ fst; snd; }` attrsets,
+	/// like `lib.zipLists`. Unlike it, lists of different lengths are an
+	/// error rather than being truncated, so no element is silently dropped.
+	pub fn zip(&self, other: &Value<'source>) -> Result<Value<'source>> {
+		let list = |value: &Value<'source>| match value {
+			Value::List(items) => Ok(items.clone()),
+			_ => Err(ValueError::TypeMismatch {
+				expected: "list".into(),
+				got: value.type_name(),
+			}),
+		};
+		let (left, right) = (list(self)?, list(other)?);
+		if left.len() != right.len() {
+			Err(ValueError::LengthMismatch {
+				left: left.len(),
+				right: right.len(),
+			})?;
+		}
+
+		Ok(Value::List(
+			left.into_iter()
+				.zip(right)
+				.map(|(fst, snd)| {
+					Value::AttrSet(Map::from([
+						(Cow::Borrowed("fst"), fst),
+						(Cow::Borrowed("snd"), snd),
+					]))
+				})
+				.collect(),
+		))
+	}
+
+	/// The list without elements structurally equal to an earlier one, as
+	/// compared by [`approx_eq`](Self::approx_eq) with no tolerance, so `1`
+	/// and `1.0` count as duplicates, like they're equal in Nix.
+	pub fn dedup_list(&self) -> Result<Value<'source>> {
+		let Value::List(items) = self else {
+			Err(ValueError::TypeMismatch {
+				expected: "list".into(),
+				got: self.type_name(),
+			})?
+		};
+
+		let mut unique: Vec<Value<'source>> = Vec::new();
+		for item in items {
+			if !unique.iter().any(|seen| seen.approx_eq(item, 0.0)) {
+				unique.push(item.clone());
+			}
+		}
+		Ok(Value::List(unique))
+	}
+
+	/// Looks up an attribute of an attrset.
+	pub fn get(&self, key: &str) -> Option<&Value<'source>> {
+		match self {
+			Value::AttrSet(map) | Value::RecAttrSet(map) => map.get(key),
+			_ => None,
+		}
+	}
+
+	/// Looks up the first of `keys` an attrset has, such as a setting and
+	/// its older aliases.
+	pub fn select_first(&self, keys: &[&str]) -> Option<&Value<'source>> {
+		keys.iter().find_map(|key| self.get(key))
+	}
+
+	/// Looks up an element of a list.
+	pub fn index(&self, index: usize) -> Option<&Value<'source>> {
+		match self {
+			Value::List(items) => items.get(index),
+			_ => None,
+		}
+	}
+
+	pub fn is_null(&self) -> bool {
+		matches!(self, Value::Null)
+	}
+
+	/// The value itself, or `default` if it's null, for optional settings.
+	pub fn unwrap_or<'a>(
+		&'a self,
+		default: &'a Value<'source>,
+	) -> &'a Value<'source> {
+		if self.is_null() {
+			default
+		} else {
+			self
+		}
+	}
+
+	/// Follows a dotted path such as `"a.b.0.c"`, where numeric segments
+	/// index into lists and other segments name attributes.
+	pub fn query(&self, path: &str) -> Option<&Value<'source>> {
+		path.split('.')
+			.try_fold(self, |value, segment| match value {
+				Value::List(_) => value.index(segment.parse().ok()?),
+				_ => value.get(segment),
+			})
+	}
+
+	/// Converts every float with an integral value, such as `3.0`, into an
+	/// integer, throughout the whole tree.
+	pub fn normalize_integral_floats(self) -> Value<'source> {
+		match self {
+			Value::Float(n)
+				if n.fract() == 0.0
+					&& n >= i64::MIN as f64
+					&& n < i64::MAX as f64 =>
+			{
+				Value::Integer(n as i64)
+			}
+			Value::List(items) => Value::List(
+				items
+					.into_iter()
+					.map(Value::normalize_integral_floats)
+					.collect(),
+			),
+			Value::AttrSet(map) => Value::AttrSet(
+				map.into_iter()
+					.map(|(key, value)| {
+						(key, value.normalize_integral_floats())
+					})
+					.collect(),
+			),
+			Value::RecAttrSet(map) => Value::RecAttrSet(
+				map.into_iter()
+					.map(|(key, value)| {
+						(key, value.normalize_integral_floats())
+					})
+					.collect(),
+			),
+			Value::LetIn(bindings, body) => Value::LetIn(
+				bindings
+					.into_iter()
+					.map(|(key, value)| {
+						(key, value.normalize_integral_floats())
+					})
+					.collect(),
+				Box::new(body.normalize_integral_floats()),
+			),
+			value => value,
+		}
+	}
+
+	/// Adds up a list of numbers, like `builtins.foldl' builtins.add 0`.
+	pub fn sum(&self) -> Result<Value<'source>> {
+		self.fold_numbers(BinOp::Add, Value::Integer(0))
+	}
+
+	/// Multiplies a list of numbers, like `builtins.foldl' builtins.mul 1`.
+	pub fn product(&self) -> Result<Value<'source>> {
+		self.fold_numbers(BinOp::Mul, Value::Integer(1))
+	}
+
+	fn fold_numbers(
+		&self,
+		op: BinOp,
+		init: Value<'source>,
+	) -> Result<Value<'source>> {
+		let Value::List(items) = self else {
+			Err(ValueError::TypeMismatch {
+				expected: "list".into(),
+				got: self.type_name(),
+			})?
+		};
+
+		Ok(items
+			.iter()
+			.try_fold(init, |acc, item| eval::binary(op, &acc, item))?)
+	}
+
+	/// Whether every element of a list of booleans is true, like
+	/// `builtins.all`. Every element must be a boolean, even after a false one.
+	pub fn all(&self) -> Result<bool> {
+		self.fold_bools(true, |acc, b| acc && b)
+	}
+
+	/// Whether any element of a list of booleans is true, like `builtins.any`.
+	/// Every element must be a boolean, even after a true one.
+	pub fn any(&self) -> Result<bool> {
+		self.fold_bools(false, |acc, b| acc || b)
+	}
+
+	fn fold_bools(
+		&self,
+		init: bool,
+		f: fn(bool, bool) -> bool,
+	) -> Result<bool> {
+		let Value::List(items) = self else {
+			Err(ValueError::TypeMismatch {
+				expected: "list".into(),
+				got: self.type_name(),
+			})?
+		};
+
+		items.iter().try_fold(init, |acc, item| match item {
+			Value::Bool(b) => Ok(f(acc, *b)),
+			_ => Err(ValueError::TypeMismatch {
+				expected: "bool".into(),
+				got: item.type_name(),
+			})?,
+		})
+	}
+
+	/// Applies `f` to every scalar leaf (null, booleans, numbers and
+	/// strings), keeping the structure around them intact. References,
+	/// functions and interpolated strings are kept as they are, since they
+	/// aren't leaves.
+	pub fn map_values<F: Fn(&Value<'source>) -> Value<'source>>(
+		&self,
+		f: &F,
+	) -> Value<'source> {
+		let map_attrs = |map: &Map<'source>| {
+			map.iter()
+				.map(|(key, value)| (key.clone(), value.map_values(f)))
+				.collect()
+		};
+
+		match self {
+			Value::List(items) => Value::List(
+				items.iter().map(|item| item.map_values(f)).collect(),
+			),
+			Value::AttrSet(map) => Value::AttrSet(map_attrs(map)),
+			Value::RecAttrSet(map) => Value::RecAttrSet(map_attrs(map)),
+			Value::LetIn(bindings, body) => {
+				Value::LetIn(map_attrs(bindings), Box::new(body.map_values(f)))
+			}
+			Value::With(scope, body) => Value::With(
+				Box::new(scope.map_values(f)),
+				Box::new(body.map_values(f)),
+			),
+			Value::If(cond, then, otherwise) => Value::If(
+				Box::new(cond.map_values(f)),
+				Box::new(then.map_values(f)),
+				Box::new(otherwise.map_values(f)),
+			),
+			Value::Select(value, path, default) => Value::Select(
+				Box::new(value.map_values(f)),
+				path.clone(),
+				default
+					.as_ref()
+					.map(|default| Box::new(default.map_values(f))),
+			),
+			Value::BinOp(op, lhs, rhs) => Value::BinOp(
+				*op,
+				Box::new(lhs.map_values(f)),
+				Box::new(rhs.map_values(f)),
+			),
+			Value::Apply(function, arg) => Value::Apply(
+				Box::new(function.map_values(f)),
+				Box::new(arg.map_values(f)),
+			),
+			Value::Ref(_)
+			| Value::Inherit(_)
+			| Value::Lambda(..)
+			| Value::Interpolate(_) => self.clone(),
+			Value::Null
+			| Value::Bool(_)
+			| Value::Integer(_)
+			| Value::Float(_)
+			| Value::RawNumber(_)
+			| Value::String(_)
+			| Value::Path(_) => f(self),
+		}
+	}
+
+	/// Structural equality where numbers only need to be within `epsilon` of
+	/// each other, so that e.g. `0.1 + 0.2` matches `0.3`. Integers are
+	/// compared with floats under the same tolerance, but exactly with each
+	/// other.
+	pub fn approx_eq(&self, other: &Value<'source>, epsilon: f64) -> bool {
+		let attrs_eq = |a: &Map<'source>, b: &Map<'source>| {
+			a.len() == b.len()
+				&& a.iter().all(|(key, value)| {
+					b.get(key)
+						.is_some_and(|other| value.approx_eq(other, epsilon))
+				})
+		};
+		let patterns_eq =
+			|a: &Pattern<'source>, b: &Pattern<'source>| match (a, b) {
+				(Pattern::Ident(a), Pattern::Ident(b)) => a == b,
+				(
+					Pattern::Set {
+						params: a,
+						ellipsis: a_ellipsis,
+						bind: a_bind,
+					},
+					Pattern::Set {
+						params: b,
+						ellipsis: b_ellipsis,
+						bind: b_bind,
+					},
+				) => {
+					a_ellipsis == b_ellipsis
+						&& a_bind == b_bind
+						&& a.len() == b.len()
+						&& a.iter().zip(b).all(|((a, x), (b, y))| {
+							a == b
+								&& match (x, y) {
+									(Some(x), Some(y)) => {
+										x.approx_eq(y, epsilon)
+									}
+									(None, None) => true,
+									_ => false,
+								}
+						})
+				}
+				_ => false,
+			};
+
+		match (self, other) {
+			(Value::Integer(a), Value::Integer(b)) => a == b,
+			(
+				Value::Integer(_) | Value::Float(_),
+				Value::Integer(_) | Value::Float(_),
+			) => match (self.as_f64(), other.as_f64()) {
+				(Some(a), Some(b)) => (a - b).abs() <= epsilon,
+				_ => false,
+			},
+			(Value::Null, Value::Null) => true,
+			(Value::Bool(a), Value::Bool(b)) => a == b,
+			(Value::RawNumber(a), Value::RawNumber(b)) => a == b,
+			(Value::String(a), Value::String(b)) => a == b,
+			(Value::Path(a), Value::Path(b)) => a == b,
+			(Value::List(a), Value::List(b)) => {
+				a.len() == b.len()
+					&& a.iter().zip(b).all(|(a, b)| a.approx_eq(b, epsilon))
+			}
+			(Value::AttrSet(a), Value::AttrSet(b))
+			| (Value::RecAttrSet(a), Value::RecAttrSet(b)) => attrs_eq(a, b),
+			(Value::LetIn(a, x), Value::LetIn(b, y)) => {
+				attrs_eq(a, b) && x.approx_eq(y, epsilon)
+			}
+			(Value::Ref(a), Value::Ref(b))
+			| (Value::Inherit(a), Value::Inherit(b)) => a == b,
+			(Value::With(a, x), Value::With(b, y)) => {
+				a.approx_eq(b, epsilon) && x.approx_eq(y, epsilon)
+			}
+			(Value::If(a, x, m), Value::If(b, y, n)) => {
+				a.approx_eq(b, epsilon)
+					&& x.approx_eq(y, epsilon)
+					&& m.approx_eq(n, epsilon)
+			}
+			(Value::Select(a, x, m), Value::Select(b, y, n)) => {
+				a.approx_eq(b, epsilon)
+					&& x == y && match (m, n) {
+					(Some(m), Some(n)) => m.approx_eq(n, epsilon),
+					(None, None) => true,
+					_ => false,
+				}
+			}
+			(Value::BinOp(op_a, a, x), Value::BinOp(op_b, b, y)) => {
+				op_a == op_b
+					&& a.approx_eq(b, epsilon)
+					&& x.approx_eq(y, epsilon)
+			}
+			(Value::Lambda(a, x), Value::Lambda(b, y)) => {
+				patterns_eq(a, b) && x.approx_eq(y, epsilon)
+			}
+			(Value::Apply(a, x), Value::Apply(b, y)) => {
+				a.approx_eq(b, epsilon) && x.approx_eq(y, epsilon)
+			}
+			(Value::Interpolate(a), Value::Interpolate(b)) => {
+				a.len() == b.len()
+					&& a.iter().zip(b).all(|(a, b)| a.approx_eq(b, epsilon))
+			}
+			_ => false,
+		}
+	}
+
+	fn as_f64(&self) -> Option<f64> {
+		match self {
+			Value::Integer(n) => Some(*n as f64),
+			Value::Float(n) => Some(*n),
+			_ => None,
+		}
+	}
+
+	/// Flattens a list of lists by one level, so `[[1 2] [3]]` becomes
+	/// `[1 2 3]`. Elements that aren't lists are kept as they are.
+	pub fn flatten(&self) -> Result<Value<'source>> {
+		let Value::List(items) = self else {
+			Err(ValueError::TypeMismatch {
+				expected: "list".into(),
+				got: self.type_name(),
+			})?
+		};
+
+		let mut result = Vec::new();
+		for item in items {
+			match item {
+				Value::List(inner) => result.extend(inner.iter().cloned()),
+				_ => result.push(item.clone()),
+			}
+		}
+
+		Ok(Value::List(result))
+	}
+
+	/// Whether the value is a leaf: null, a bool, a number, a string or a
+	/// path.
+	///
+	/// Containers and unevaluated expressions are not scalars.
+	pub fn is_scalar(&self) -> bool {
+		matches!(
+			self,
+			Value::Null
+				| Value::Bool(_)
+				| Value::Integer(_)
+				| Value::Float(_)
+				| Value::RawNumber(_)
+				| Value::String(_)
+				| Value::Path(_)
+		)
+	}
+
+	/// Keeps only the attributes for which `pred` returns true, throughout
+	/// the whole tree, including attrsets nested in lists. Anything else is
+	/// returned unchanged.
+	pub fn retain_attrs<F: Fn(&str, &Value<'source>) -> bool>(
+		self,
+		pred: F,
+	) -> Value<'source> {
+		self.retain_attrs_with(&pred)
+	}
+
+	fn retain_attrs_with<F: Fn(&str, &Value<'source>) -> bool>(
+		self,
+		pred: &F,
+	) -> Value<'source> {
+		let retain = |map: Map<'source>| -> Map<'source> {
+			map.into_iter()
+				.filter(|(key, value)| pred(key, value))
+				.map(|(key, value)| (key, value.retain_attrs_with(pred)))
+				.collect()
+		};
+
+		match self {
+			Value::AttrSet(map) => Value::AttrSet(retain(map)),
+			Value::RecAttrSet(map) => Value::RecAttrSet(retain(map)),
+			Value::List(items) => Value::List(
+				items
+					.into_iter()
+					.map(|item| item.retain_attrs_with(pred))
+					.collect(),
+			),
+			other => other,
+		}
+	}
+
+	/// The type name shared by every element of a list, or `None` if the
+	/// elements are mixed, the list is empty or this isn't a list.
+	pub fn is_homogeneous_list(&self) -> Option<&'static str> {
+		let Value::List(items) = self else {
+			return None;
+		};
+		let first = items.first()?.type_name();
+		items
+			.iter()
+			.all(|item| item.type_name() == first)
+			.then_some(first)
+	}
+
+	/// Merges `other` over `self`: attributes in both attrsets are merged
+	/// recursively, and anything else in `other` replaces what's in `self`.
+	pub fn deep_merge(self, other: Value<'source>) -> Value<'source> {
+		match (self, other) {
+			(Value::AttrSet(mut base), Value::AttrSet(overrides)) => {
+				for (key, value) in overrides {
+					let merged = match base.remove(&key) {
+						Some(existing) => existing.deep_merge(value),
+						None => value,
+					};
+					base.insert(key, merged);
+				}
+				Value::AttrSet(base)
+			}
+			(_, other) => other,
+		}
+	}
+
+	/// Fills in the attributes missing from `self`, throughout nested
+	/// attrsets, from `defaults`, without overriding any that are present;
+	/// the reverse of [`deep_merge`](Self::deep_merge)'s precedence.
+	pub fn apply_defaults(&self, defaults: &Value<'source>) -> Value<'source> {
+		defaults.clone().deep_merge(self.clone())
+	}
+
+	/// Merges `other` with `self` like [`deep_merge`](Self::deep_merge), but
+	/// lets `resolver` decide each conflict, given the path to it and the
+	/// values from `self` and `other`, instead of `other` always winning.
+	pub fn merge_with<F>(
+		&self,
+		other: &Value<'source>,
+		resolver: F,
+	) -> Value<'source>
+	where
+		F: Fn(&[&str], &Value<'source>, &Value<'source>) -> Value<'source>,
+	{
+		self.merge_at(other, &mut Vec::new(), &resolver)
+	}
+
+	fn merge_at<'a, F>(
+		&'a self,
+		other: &'a Value<'source>,
+		path: &mut Vec<&'a str>,
+		resolver: &F,
+	) -> Value<'source>
+	where
+		F: Fn(&[&str], &Value<'source>, &Value<'source>) -> Value<'source>,
+	{
+		match (self, other) {
+			(Value::AttrSet(base), Value::AttrSet(overrides)) => {
+				let mut merged = base.clone();
+				for (key, value) in overrides {
+					let value = match base.get(key) {
+						Some(existing) => {
+							path.push(key);
+							let value =
+								existing.merge_at(value, path, resolver);
+							path.pop();
+							value
+						}
+						None => value.clone(),
+					};
+					merged.insert(key.clone(), value);
+				}
+				Value::AttrSet(merged)
+			}
+			_ => resolver(path, self, other),
+		}
+	}
+
+	/// The path as written, if this is a path.
+	pub fn as_path(&self) -> Option<&str> {
+		match self {
+			Value::Path(path) => Some(path),
+			_ => None,
+		}
+	}
+
+	/// Resolves a path the way Nix does: relative paths against `base`, the
+	/// directory of the file they appear in, and `~/` paths against the home
+	/// directory. Returns `None` if this isn't a path, or `HOME` is needed
+	/// but unset.
+	pub fn to_path_buf(&self, base: &Path) -> Option<PathBuf> {
+		let path = self.as_path()?;
+		if let Some(rest) = path.strip_prefix("~/") {
+			return Some(PathBuf::from(std::env::var_os("HOME")?).join(rest));
+		}
+
+		let mut resolved = if path.starts_with('/') {
+			PathBuf::from("/")
+		} else {
+			base.to_path_buf()
+		};
+		for component in Path::new(path).components() {
+			match component {
+				Component::ParentDir => {
+					resolved.pop();
+				}
+				Component::Normal(name) => resolved.push(name),
+				Component::RootDir
+				| Component::CurDir
+				| Component::Prefix(_) => (),
+			}
+		}
+		Some(resolved)
+	}
+
+	/// Splits an attrset in two: the attributes whose names start with
+	/// `prefix`, with it stripped, and the rest. Anything other than an
+	/// attrset is all rest.
+	pub fn split_by_prefix(
+		&self,
+		prefix: &str,
+	) -> (Value<'source>, Value<'source>) {
+		let (Value::AttrSet(map) | Value::RecAttrSet(map)) = self else {
+			return (Value::AttrSet(Map::new()), self.clone());
+		};
+
+		let mut matching = Map::new();
+		let mut rest = Map::new();
+		for (key, value) in map {
+			match key.strip_prefix(prefix) {
+				Some(stripped) => {
+					matching.insert(stripped.to_owned().into(), value.clone())
+				}
+				None => rest.insert(key.clone(), value.clone()),
+			};
+		}
+		(Value::AttrSet(matching), Value::AttrSet(rest))
+	}
+
+	/// Renames the attribute `from` of an attrset to `to`. Nothing changes
+	/// if `to` is already taken, `from` doesn't exist, or this isn't an
+	/// attrset, so a rename never loses a value.
+	pub fn rename_attr(self, from: &str, to: &str) -> Value<'source> {
+		match self {
+			Value::AttrSet(mut map) => {
+				rename_key(&mut map, from, to);
+				Value::AttrSet(map)
+			}
+			Value::RecAttrSet(mut map) => {
+				rename_key(&mut map, from, to);
+				Value::RecAttrSet(map)
+			}
+			other => other,
+		}
+	}
+
+	/// Flattens nested attrsets into environment variables, so that
+	/// `{ db = { port = 5432; }; }` becomes `PREFIX_DB_PORT=5432`, sorted by
+	/// name. Names are uppercased, with anything but letters and digits
+	/// replaced by `_`.
+	///
+	/// Null becomes an empty string. Lists, whose elements have no single
+	/// obvious separator, and unevaluated expressions are errors, as are two
+	/// attributes that give the same name, such as `a-b` and `a_b`.
+	pub fn to_env_vars(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+		let mut vars = Vec::new();
+		self.collect_env_vars(prefix.to_owned(), String::new(), &mut vars)?;
+		// Sorted by path too, so a collision is reported the same way every
+		// time.
+		vars.sort();
+		if let Some(pair) = vars.windows(2).find(|pair| pair[0].0 == pair[1].0)
+		{
+			Err(ValueError::KeyCollision {
+				first: pair[0].1.clone(),
+				second: pair[1].1.clone(),
+				renamed: pair[0].0.clone(),
+			})?
+		}
+		Ok(vars
+			.into_iter()
+			.map(|(name, _, value)| (name, value))
+			.collect())
+	}
+
+	/// Collects the variables of the value at `path`, as
+	/// `(name, path, value)`.
+	fn collect_env_vars(
+		&self,
+		name: String,
+		path: String,
+		vars: &mut Vec<(String, String, String)>,
+	) -> Result<()> {
+		let value = match self {
+			Value::AttrSet(map) | Value::RecAttrSet(map) => {
+				for (key, value) in map {
+					let path = if path.is_empty() {
+						key.to_string()
+					} else {
+						format!("{path}.{key}")
+					};
+					let key = key
+						.chars()
+						.map(|c| {
+							if c.is_ascii_alphanumeric() {
+								c.to_ascii_uppercase()
+							} else {
+								'_'
+							}
+						})
+						.collect::<String>();
+					let name = if name.is_empty() {
+						key
+					} else {
+						format!("{name}_{key}")
+					};
+					value.collect_env_vars(name, path, vars)?;
+				}
+				return Ok(());
+			}
+			_ => {
+				self.scalar_text()
+					.ok_or_else(|| ValueError::NotExportable {
+						name: name.clone(),
+						got: self.type_name(),
+					})?
+			}
+		};
+		vars.push((name, path, value));
+		Ok(())
+	}
+
+	/// Writes a flat attrset of scalars as `key<sep>value` lines, sorted by
+	/// key, as in `.env` or `.properties` files. Values are written like
+	/// [`to_env_vars`](Self::to_env_vars) does; a list or nested attrset is
+	/// an error.
+	pub fn to_key_value_lines(&self, sep: char) -> Result<String> {
+		let got = self.type_name();
+		let (Value::AttrSet(map) | Value::RecAttrSet(map)) = self else {
+			Err(ValueError::TypeMismatch {
+				expected: "set".into(),
+				got,
+			})?
+		};
+
+		let mut keys = map.keys().collect::<Vec<_>>();
+		keys.sort();
+		let mut out = String::new();
+		for key in keys {
+			let value = &map[key];
+			let Some(text) = value.scalar_text() else {
+				Err(ValueError::NotFlat {
+					name: key.to_string(),
+					got: value.type_name(),
+				})?
+			};
+			out.push_str(&format!("{key}{sep}{text}\n"));
+		}
+		Ok(out)
+	}
+
+	/// A scalar as plain text: null is empty, and strings and paths are
+	/// written without quotes.
+	fn scalar_text(&self) -> Option<String> {
+		match self {
+			Value::Null => Some(String::new()),
+			Value::Bool(b) => Some(b.to_string()),
+			Value::Integer(n) => Some(n.to_string()),
+			Value::Float(n) => Some(n.to_string()),
+			Value::RawNumber(s) | Value::String(s) | Value::Path(s) => {
+				Some(s.to_string())
+			}
+			_ => None,
+		}
+	}
+
+	/// Coerces the value to a string the way Nix does for interpolation:
+	/// strings are themselves, paths are the path as written (Nix would
+	/// copy it to the store first) and attrsets coerce their `outPath`.
+	/// Anything else, numbers and bools included, is an error.
+	pub fn coerce_to_string(&self) -> Result<String> {
+		match self {
+			Value::String(s) | Value::Path(s) => Ok(s.to_string()),
+			Value::AttrSet(map) | Value::RecAttrSet(map)
+				if map.contains_key("outPath") =>
+			{
+				map["outPath"].coerce_to_string()
+			}
+			_ => Err(ValueError::NotCoercible(self.type_name()))?,
+		}
+	}
+
+	/// The bindings of an attrset, for editing it in place.
+	pub fn as_attrset_mut(&mut self) -> Option<&mut Map<'source>> {
+		match self {
+			Value::AttrSet(map) | Value::RecAttrSet(map) => Some(map),
+			_ => None,
+		}
+	}
+
+	/// Sets an attribute of an attrset, returning the value it replaced.
+	pub fn insert(
+		&mut self,
+		key: impl Into<Cow<'source, str>>,
+		value: Value<'source>,
+	) -> Result<Option<Value<'source>>> {
+		let got = self.type_name();
+		let Some(map) = self.as_attrset_mut() else {
+			Err(ValueError::TypeMismatch {
+				expected: "set".into(),
+				got,
+			})?
+		};
+		Ok(map.insert(key.into(), value))
+	}
+
+	/// The attrset with an attribute set to `value`, for building sets in a
+	/// chain, as in `set.with_attr("a", 1).with_attr("b", 2)`.
+	///
+	/// # Panics
+	///
+	/// Panics if the value isn't an attrset, since a chain has nowhere to
+	/// return an error; use [`insert`](Self::insert) to handle one.
+	pub fn with_attr(
+		mut self,
+		key: impl Into<Cow<'source, str>>,
+		value: impl Into<Value<'source>>,
+	) -> Value<'source> {
+		let got = self.type_name();
+		let Some(map) = self.as_attrset_mut() else {
+			panic!("with_attr called on a {got}, not a set");
+		};
+		map.insert(key.into(), value.into());
+		self
+	}
+
+	/// Counts the values of each type, by [`type_name`](Self::type_name),
+	/// throughout lists and attrsets, the containers themselves included.
+	pub fn type_histogram(&self) -> HashMap<&'static str, usize> {
+		let mut histogram = HashMap::new();
+		self.count_types(&mut histogram);
+		histogram
+	}
+
+	fn count_types(&self, histogram: &mut HashMap<&'static str, usize>) {
+		*histogram.entry(self.type_name()).or_default() += 1;
+		match self {
+			Value::List(items) => {
+				items.iter().for_each(|item| item.count_types(histogram))
+			}
+			Value::AttrSet(map) | Value::RecAttrSet(map) => {
+				map.values().for_each(|value| value.count_types(histogram))
+			}
+			_ => (),
+		}
+	}
+
+	/// Removes every attribute whose value is null, throughout the whole tree,
+	/// for tidier exports of optional settings.
+	///
+	/// Nulls in lists are kept, since removing them would shift the elements
+	/// after them.
+	pub fn prune_nulls(self) -> Value<'source> {
+		self.retain_attrs(|_, value| !value.is_null())
+	}
+
+	/// The path to every value that isn't an attrset, through nested
+	/// attrsets, sorted. Lists are leaves; an empty attrset has none, so it
+	/// contributes no paths.
+	pub fn attr_paths(&self) -> Vec<Vec<String>> {
+		let mut paths = Vec::new();
+		self.collect_attr_paths(&mut Vec::new(), &mut paths);
+		paths.sort();
+		paths
+	}
+
+	fn collect_attr_paths(
+		&self,
+		path: &mut Vec<String>,
+		paths: &mut Vec<Vec<String>>,
+	) {
+		match self {
+			Value::AttrSet(map) | Value::RecAttrSet(map) => {
+				for (key, value) in map {
+					path.push(key.to_string());
+					value.collect_attr_paths(path, paths);
+					path.pop();
+				}
+			}
+			_ => paths.push(path.clone()),
+		}
+	}
+
+	/// Flattens nested attrsets into a map from `sep`-joined key paths to
+	/// scalars, so `{ a = { b = 1; }; }` becomes `"a.b"` for `.`. The keys
+	/// are the [`attr_paths`](Self::attr_paths), joined.
+	///
+	/// Lists are errors rather than being indexed, since a flat consumer has
+	/// no way to tell `a.0` the list element from `a."0"` the attribute, and
+	/// so are unevaluated expressions. So are two paths that join to the same
+	/// key, as `"a.b"` and `a.b` do, rather than one replacing the other.
+	pub fn to_flat_map(
+		&self,
+		sep: char,
+	) -> Result<HashMap<String, Value<'source>>> {
+		let mut flat = HashMap::new();
+		self.collect_flat(&mut Vec::new(), sep, &mut flat)?;
+		Ok(flat
+			.into_iter()
+			.map(|(key, (_, value))| (key, value))
+			.collect())
+	}
+
+	/// Adds the scalars under `path` to `flat`, by their keys, along with
+	/// the paths they were found at, written with any name that contains
+	/// `sep` quoted.
+	fn collect_flat<'a>(
+		&'a self,
+		path: &mut Vec<&'a str>,
+		sep: char,
+		flat: &mut HashMap<String, (String, Value<'source>)>,
+	) -> Result<()> {
+		let sep_str = sep.to_string();
+		match self {
+			Value::AttrSet(map) | Value::RecAttrSet(map) => {
+				let mut keys = map.keys().collect::<Vec<_>>();
+				keys.sort();
+				for key in keys {
+					path.push(key);
+					map[key].collect_flat(path, sep, flat)?;
+					path.pop();
+				}
+			}
+			value if value.is_scalar() => {
+				let key = path.join(&sep_str);
+				let shown = path
+					.iter()
+					.map(|name| {
+						if name.contains(sep) {
+							format!("{name:?}")
+						} else {
+							name.to_string()
+						}
+					})
+					.collect::<Vec<_>>()
+					.join(&sep_str);
+				match flat.entry(key) {
+					Entry::Occupied(entry) => {
+						Err(ValueError::FlatKeyCollision {
+							first: entry.get().0.clone(),
+							second: shown,
+							key: entry.key().clone(),
+						})?
+					}
+					Entry::Vacant(entry) => {
+						entry.insert((shown, value.clone()));
+					}
+				}
+			}
+			value => Err(ValueError::NotScalar {
+				path: path.join(&sep_str),
+				got: value.type_name(),
+			})?,
+		}
+		Ok(())
+	}
+
+	/// Every scalar in the tree, in pre-order, with attributes visited in
+	/// order of their names. Unevaluated expressions aren't scalars, so
+	/// they're skipped along with anything in them.
+	pub fn leaves(&self) -> impl Iterator<Item = &Value<'source>> + '_ {
+		let mut stack = vec![self];
+		std::iter::from_fn(move || {
+			while let Some(value) = stack.pop() {
+				match value {
+					Value::List(items) => stack.extend(items.iter().rev()),
+					Value::AttrSet(map) | Value::RecAttrSet(map) => {
+						let mut keys = map.keys().collect::<Vec<_>>();
+						keys.sort();
+						stack.extend(
+							keys.into_iter().rev().map(|key| &map[key]),
+						);
+					}
+					value if value.is_scalar() => return Some(value),
+					_ => (),
+				}
+			}
+			None
+		})
+	}
+
+	/// The number of [`leaves`](Self::leaves) in the tree.
+	pub fn leaf_count(&self) -> usize {
+		self.leaves().count()
+	}
+
+	/// Pairs of attribute paths, through nested attrsets, that hold
+	/// structurally equal values, as a hint of copy-pasted config. Once two
+	/// attrsets are reported, the attributes within them aren't also
+	/// reported as pairs of their own.
+	pub fn find_duplicate_values(&self) -> Vec<(Vec<String>, Vec<String>)> {
+		let mut entries = Vec::new();
+		self.collect_entries(&mut Vec::new(), &mut entries);
+
+		let mut duplicates: Vec<(Vec<String>, Vec<String>)> = Vec::new();
+		for (i, (path, value)) in entries.iter().enumerate() {
+			for (other_path, other) in &entries[i + 1..] {
+				let covered = duplicates.iter().any(|(a, b)| {
+					path.starts_with(a) && other_path.starts_with(b)
+				});
+				if !covered
+					&& !other_path.starts_with(path)
+					&& value.approx_eq(other, 0.0)
+				{
+					duplicates.push((path.clone(), other_path.clone()));
+				}
+			}
+		}
+		duplicates
+	}
+
+	/// Every attribute value in nested attrsets, in pre-order, with
+	/// attributes visited in order of their names.
+	fn collect_entries<'a>(
+		&'a self,
+		path: &mut Vec<String>,
+		entries: &mut Vec<(Vec<String>, &'a Value<'source>)>,
+	) {
+		if let Value::AttrSet(map) | Value::RecAttrSet(map) = self {
+			let mut keys = map.keys().collect::<Vec<_>>();
+			keys.sort();
+			for key in keys {
+				path.push(key.to_string());
+				entries.push((path.clone(), &map[key]));
+				map[key].collect_entries(path, entries);
+				path.pop();
+			}
+		}
+	}
+
+	/// The number of attributes throughout the tree, counting those of
+	/// every nested attrset, including attrsets in lists.
+	pub fn total_attrs(&self) -> usize {
+		match self {
+			Value::List(items) => items.iter().map(Value::total_attrs).sum(),
+			Value::AttrSet(map) | Value::RecAttrSet(map) => {
+				map.len() + map.values().map(Value::total_attrs).sum::<usize>()
+			}
+			_ => 0,
+		}
+	}
+
+	/// Folds every one of the [`leaves`](Self::leaves) into an accumulator.
+	pub fn fold<B, F: FnMut(B, &Value<'source>) -> B>(
+		&self,
+		init: B,
+		f: F,
+	) -> B {
+		self.leaves().fold(init, f)
+	}
+
+	/// Like [`fold`](Self::fold), but stops at the first error `f` returns.
+	pub fn try_fold<B, E, F>(&self, init: B, mut f: F) -> Result<B, E>
+	where
+		F: FnMut(B, &Value<'source>) -> Result<B, E>,
+	{
+		let mut acc = init;
+		for leaf in self.leaves() {
+			acc = f(acc, leaf)?;
+		}
+		Ok(acc)
+	}
+
+	/// Renames every attribute with `f`, throughout the whole tree,
+	/// including attrsets nested in lists, such as to make names safe for
+	/// environment variables. Two attributes of an attrset that `f` renames
+	/// alike are an error rather than one silently replacing the other.
+	pub fn sanitize_keys<F: Fn(&str) -> String>(
+		self,
+		f: F,
+	) -> Result<Value<'source>> {
+		self.sanitize_keys_with(&f)
+	}
+
+	fn sanitize_keys_with<F: Fn(&str) -> String>(
+		self,
+		f: &F,
+	) -> Result<Value<'source>> {
+		let sanitize = |map: Map<'source>| -> Result<Map<'source>> {
+			let mut entries = map.into_iter().collect::<Vec<_>>();
+			entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+			let mut renamed = Map::new();
+			let mut originals: HashMap<String, Cow<str>> = HashMap::new();
+			for (key, value) in entries {
+				let new_key = f(&key);
+				if let Some(first) = originals.get(&new_key) {
+					Err(ValueError::KeyCollision {
+						first: first.to_string(),
+						second: key.to_string(),
+						renamed: new_key.clone(),
+					})?
+				}
+				renamed.insert(
+					Cow::Owned(new_key.clone()),
+					value.sanitize_keys_with(f)?,
+				);
+				originals.insert(new_key, key);
+			}
+			Ok(renamed)
+		};
+
+		Ok(match self {
+			Value::AttrSet(map) => Value::AttrSet(sanitize(map)?),
+			Value::RecAttrSet(map) => Value::RecAttrSet(sanitize(map)?),
+			Value::List(items) => Value::List(
+				items
+					.into_iter()
+					.map(|item| item.sanitize_keys_with(f))
+					.collect::<Result<_>>()?,
+			),
+			other => other,
+		})
+	}
+
+	/// Groups a list of attrsets by their string attribute `key`, into an
+	/// attrset from each value of it to the elements that have that value,
+	/// in their original order, like `builtins.groupBy`.
+	pub fn group_by(&self, key: &str) -> Result<Value<'source>> {
+		let Value::List(items) = self else {
+			Err(ValueError::TypeMismatch {
+				expected: "list".into(),
+				got: self.type_name(),
+			})?
+		};
+
+		let mut groups: HashMap<_, Vec<_>> = HashMap::new();
+		for item in items {
+			let (Value::AttrSet(map) | Value::RecAttrSet(map)) = item else {
+				Err(ValueError::TypeMismatch {
+					expected: "set".into(),
+					got: item.type_name(),
+				})?
+			};
+			let group = match map.get(key) {
+				Some(Value::String(group)) => group.clone(),
+				Some(other) => Err(ValueError::TypeMismatch {
+					expected: "string".into(),
+					got: other.type_name(),
+				})?,
+				None => Err(ValueError::MissingAttribute(key.to_owned()))?,
+			};
+			groups.entry(group).or_default().push(item.clone());
+		}
+		Ok(Value::AttrSet(
+			groups
+				.into_iter()
+				.map(|(group, members)| (group, Value::List(members)))
+				.collect(),
+		))
+	}
+}
+
+fn rename_key(map: &mut Map, from: &str, to: &str) {
+	if map.contains_key(to) {
+		return;
+	}
+	if let Some(value) = map.remove(from) {
+		map.insert(Cow::Owned(to.to_owned()), value);
+	}
+}
+
+impl From<bool> for Value<'_> {
+	fn from(b: bool) -> Self {
+		Value::Bool(b)
+	}
+}
+
+impl From<i32> for Value<'_> {
+	fn from(n: i32) -> Self {
+		Value::Integer(n.into())
+	}
+}
+
+impl From<i64> for Value<'_> {
+	fn from(n: i64) -> Self {
+		Value::Integer(n)
+	}
+}
+
+impl From<f64> for Value<'_> {
+	fn from(n: f64) -> Self {
+		Value::Float(n)
+	}
+}
+
+impl<'source> From<&'source str> for Value<'source> {
+	fn from(s: &'source str) -> Self {
+		Value::String(Cow::Borrowed(s))
+	}
+}
+
+impl From<String> for Value<'_> {
+	fn from(s: String) -> Self {
+		Value::String(Cow::Owned(s))
+	}
+}
+
+/// Builds a [`Value`] from JSON-like syntax, for tests and embedders, as in
+/// `nix_value!({ a: 1, b: [true, null] })`.
+///
+/// Attribute names are identifiers or string literals. Any other Rust
+/// expression, such as a variable or a parenthesized `(-1)`, is converted
+/// with [`From`].
+#[macro_export]
+macro_rules! nix_value {
+	(null) => {
+		$crate::value::Value::Null
+	};
+	([ $($item:tt),* $(,)? ]) => {
+		$crate::value::Value::List(vec![$($crate::nix_value!($item)),*])
+	};
+	({ $($key:tt : $value:tt),* $(,)? }) => {{
+		#[allow(unused_mut)]
+		let mut map = $crate::value::Map::new();
+		$(
+			map.insert(
+				::std::borrow::Cow::from($crate::nix_value!(@key $key)),
+				$crate::nix_value!($value),
+			);
+		)*
+		$crate::value::Value::AttrSet(map)
+	}};
+	(@key $key:ident) => {
+		stringify!($key)
+	};
+	(@key $key:literal) => {
+		$key
+	};
+	($other:expr) => {
+		$crate::value::Value::from($other)
+	};
+}
@@ -0,0 +1,43 @@
+use nixc::{diff::Change, nix_value, parser::Parser, value::Value};
+
+fn parse(src: &str) -> Value<'_> {
+	Parser::default()
+		.parse(src, "test.nix")
+		.expect("should parse")
+}
+
+#[test]
+fn diff_reports_modified_and_added_attributes() {
+	let old = parse("{ a = 1; b = 2; }");
+	let new = parse("{ a = 1; b = 3; c = 4; }");
+	let changes = old.diff(&new);
+
+	assert_eq!(changes.len(), 2, "{changes:?}");
+	assert!(matches!(
+		&changes[0],
+		Change::Modified { path, old: Value::Integer(2), new: Value::Integer(3) }
+			if path == "b"
+	));
+	assert!(matches!(
+		&changes[1],
+		Change::Added { path, value: Value::Integer(4) } if path == "c"
+	));
+}
+
+#[test]
+fn diff_reports_nested_and_list_changes_by_path() {
+	let old = nix_value!({ a: { b: [1, 2] }, gone: true });
+	let new = nix_value!({ a: { b: [1] } });
+	let changes = old.diff(&new);
+
+	assert_eq!(changes.len(), 2, "{changes:?}");
+	assert!(matches!(
+		&changes[0],
+		Change::Removed { path, value: Value::Integer(2) } if path == "a.b.1"
+	));
+	assert!(matches!(
+		&changes[1],
+		Change::Removed { path, value: Value::Bool(true) } if path == "gone"
+	));
+	assert!(old.diff(&old).is_empty());
+}
@@ -0,0 +1,195 @@
+use miette::Report;
+use nixc::{
+	eval::{self, BinOp, EvalError},
+	nix_value,
+	parser::Parser,
+	value::Value,
+};
+
+fn parse_err(src: &str) -> Report {
+	Parser::default()
+		.parse(src, "test.nix")
+		.expect_err("should fail to parse")
+}
+
+/// The offset and length of the span an error points at.
+fn span(err: &Report) -> (usize, usize) {
+	let label = err.labels().and_then(|mut labels| labels.next());
+	let label = label.expect("error has a label");
+	(label.offset(), label.len())
+}
+
+#[test]
+fn overflowing_addition_is_an_error() {
+	let err = parse_err("9223372036854775807 + 1");
+	assert_eq!(err.to_string(), "parse error: integer overflow in addition");
+	assert_eq!(span(&err), (20, 1));
+}
+
+#[test]
+fn overflowing_multiplication_is_an_error() {
+	let err = parse_err("9223372036854775807 * 2");
+	assert_eq!(
+		err.to_string(),
+		"parse error: integer overflow in multiplication"
+	);
+	assert_eq!(span(&err), (20, 1));
+}
+
+#[test]
+fn checked_arithmetic_reports_the_operation() {
+	let max = Value::Integer(i64::MAX);
+	assert_eq!(
+		eval::binary(BinOp::Add, &max, &Value::Integer(1)).unwrap_err(),
+		EvalError::Overflow("addition")
+	);
+	assert_eq!(
+		eval::binary(BinOp::Mul, &max, &Value::Integer(2)).unwrap_err(),
+		EvalError::Overflow("multiplication")
+	);
+	assert!(matches!(
+		eval::binary(BinOp::Add, &Value::Integer(1), &Value::Integer(2)),
+		Ok(Value::Integer(3))
+	));
+}
+
+fn canonicalize(src: &str) -> Result<Value<'_>, EvalError> {
+	Parser::default()
+		.parse(src, "test.nix")
+		.expect("should parse")
+		.canonicalize()
+}
+
+#[test]
+fn canonicalize_resolves_let_bindings() {
+	let value = canonicalize("let x = 1; in { a = x; }").unwrap();
+	assert!(value.approx_eq(&nix_value!({ a: 1 }), 0.0), "{value:?}");
+}
+
+#[test]
+fn canonicalize_rejects_an_undefined_variable() {
+	assert_eq!(
+		canonicalize("{ a = x; }").unwrap_err(),
+		EvalError::UndefinedVariable("x".into())
+	);
+}
+
+#[test]
+fn a_binding_to_its_own_name_is_infinite_recursion() {
+	for src in ["let x = x; in x", "rec { x = x; }"] {
+		assert_eq!(
+			canonicalize(src).unwrap_err(),
+			EvalError::InfiniteRecursion("x".into())
+		);
+	}
+	// Unlike `inherit x`, which refers to the `x` around it.
+	assert!(matches!(
+		canonicalize("let x = 1; in let inherit x; in x"),
+		Ok(Value::Integer(1))
+	));
+	let value = canonicalize("let x = 1; in rec { inherit x; }").unwrap();
+	assert!(value.approx_eq(&nix_value!({ x: 1 }), 0.0), "{value:?}");
+}
+
+#[test]
+fn let_bound_functions_can_be_called() {
+	assert!(matches!(
+		canonicalize("let f = x: x; in f 1"),
+		Ok(Value::Integer(1))
+	));
+	assert!(matches!(
+		canonicalize("let add = a: b: a + b; in add 1 2"),
+		Ok(Value::Integer(3))
+	));
+	// The lambda's body ends at the `;` of its binding.
+	let Value::LetIn(bindings, body) =
+		canonicalize("let f = x: x + 1; in f").unwrap()
+	else {
+		panic!("expected the function in its scope");
+	};
+	assert!(matches!(bindings["f"], Value::Lambda(..)));
+	assert!(matches!(*body, Value::Lambda(..)));
+}
+
+#[test]
+fn functions_see_the_scope_they_are_defined_in() {
+	assert!(matches!(
+		canonicalize("let f = x: y; y = 1; in let y = 2; in f 0"),
+		Ok(Value::Integer(1))
+	));
+	assert!(matches!(
+		canonicalize(
+			"let s = let y = 1; in { f = x: y; }; in let y = 2; in s.f 0"
+		),
+		Ok(Value::Integer(1))
+	));
+	assert!(matches!(
+		canonicalize("with { y = 1; }; let f = x: y; in with { y = 2; }; f 0"),
+		Ok(Value::Integer(1))
+	));
+}
+
+#[test]
+fn functions_do_not_see_the_scope_they_are_called_in() {
+	assert_eq!(
+		canonicalize("let f = x: y; in let y = 2; in f 0").unwrap_err(),
+		EvalError::UndefinedVariable("y".into())
+	);
+	// A binding of the same name as one being resolved is a different one.
+	assert!(matches!(
+		canonicalize("let h = (let x = 2; in y: x); in let x = h 0; in x"),
+		Ok(Value::Integer(2))
+	));
+}
+
+#[test]
+fn recursion_through_calls_is_an_error() {
+	assert_eq!(
+		canonicalize("let x = (y: x) 1; in x").unwrap_err(),
+		EvalError::CallDepth(eval::MAX_CALL_DEPTH)
+	);
+}
+
+#[test]
+fn interpolation_coerces_strings_and_paths() {
+	let value = canonicalize(r#"let s = "x"; in "a ${s} b""#).unwrap();
+	assert!(matches!(value, Value::String(s) if s == "a x b"));
+	let value = canonicalize(r#"let p = ./dir; in "${p}/file""#).unwrap();
+	assert!(matches!(value, Value::String(s) if s == "./dir/file"));
+}
+
+#[test]
+fn interpolation_rejects_an_integer() {
+	assert_eq!(
+		canonicalize(r#"let n = 1; in "n = ${n}""#).unwrap_err(),
+		EvalError::NotCoercible("int")
+	);
+}
+
+#[test]
+fn inner_with_scopes_shadow_outer_ones() {
+	let value =
+		canonicalize("with { x = 1; y = 2; }; with { x = 3; }; [ x y ]")
+			.unwrap();
+	assert!(value.approx_eq(&nix_value!([3, 2]), 0.0), "{value:?}");
+}
+
+#[test]
+fn let_and_arguments_shadow_every_with() {
+	let value =
+		canonicalize("let x = 1; in with { x = 2; }; with { x = 3; }; x")
+			.unwrap();
+	assert!(matches!(value, Value::Integer(1)));
+	let value =
+		canonicalize("with { x = 2; }; let x = 1; in with { x = 3; }; x")
+			.unwrap();
+	assert!(matches!(value, Value::Integer(1)));
+	let value = canonicalize("(x: with { x = 2; }; x) 1").unwrap();
+	assert!(matches!(value, Value::Integer(1)));
+	// Names bound nowhere else still come from a `with`.
+	let value = canonicalize(
+		"let x = 1; in with { y = 2; }; with { z = 3; }; x + y + z",
+	)
+	.unwrap();
+	assert!(matches!(value, Value::Integer(6)));
+}
@@ -0,0 +1,64 @@
+use nixc::{nix_value, parser::Parser, value::Value};
+
+fn parse(src: &str) -> Value<'_> {
+	Parser::default()
+		.parse(src, "test.nix")
+		.expect("should parse")
+}
+
+#[test]
+fn inherit_without_a_scope_names_the_key() {
+	let value = parse("{ a = 1; inherit x; }");
+	let err = value.to_json(None).unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"cannot export `x`, which refers to the enclosing scope, without a \
+		 scope"
+	);
+}
+
+#[test]
+fn inherit_is_looked_up_in_the_given_scope() {
+	let value = parse("{ a = 1; inherit x; }");
+	let scope = nix_value!({ x: [true, null] });
+	assert_eq!(
+		value.to_json(Some(&scope)).unwrap(),
+		r#"{"a":1,"x":[true,null]}"#
+	);
+
+	let err = value.to_json(Some(&nix_value!({}))).unwrap_err();
+	assert_eq!(err.to_string(), "`x` is not defined in the given scope");
+}
+
+#[test]
+fn from_json_str_matches_the_equivalent_nix() {
+	let json = nixc::from_json_str(
+		r#"{ "name": "web", "port": 8080, "ratio": 0.5, "tags": ["a", "b"],
+		     "tls": { "enable": true, "cert": null } }"#,
+	)
+	.unwrap();
+	let nix = parse(
+		r#"{ name = "web"; port = 8080; ratio = 0.5; tags = [ "a" "b" ];
+		     tls = { enable = true; cert = null; }; }"#,
+	);
+	assert!(json.approx_eq(&nix, 0.0), "{json:?} is not {nix:?}");
+}
+
+#[test]
+fn from_json_str_decodes_escapes() {
+	let value = nixc::from_json_str(r#""a\n\"é😀""#).unwrap();
+	assert!(matches!(value, Value::String(s) if s == "a\n\"é😀"));
+}
+
+#[test]
+fn from_json_str_replaces_lone_surrogates() {
+	let decode = |json| match nixc::from_json_str(json).unwrap() {
+		Value::String(s) => s.into_owned(),
+		other => panic!("expected a string, got {other:?}"),
+	};
+	assert_eq!(decode(r#""\ud83d\ude00""#), "😀");
+	assert_eq!(decode(r#""\ud800\u0041""#), "\u{FFFD}A");
+	assert_eq!(decode(r#""\ud800ab""#), "\u{FFFD}ab");
+	assert_eq!(decode(r#""\udc00x""#), "\u{FFFD}x");
+	assert_eq!(decode(r#""x\ud800""#), "x\u{FFFD}");
+}
@@ -0,0 +1,91 @@
+use logos::Logos;
+use nixc::{lexer::Token, parser::Parser};
+
+fn tokens(src: &str) -> Vec<Token<'_>> {
+	Token::lexer(src)
+		.map(|token| token.expect("should lex"))
+		.collect()
+}
+
+#[test]
+fn numbers_running_into_names_are_one_invalid_token() {
+	assert_eq!(tokens("1abc"), [Token::InvalidNumber("1abc")]);
+	assert_eq!(tokens("0x1g"), [Token::InvalidNumber("0x1g")]);
+	assert_eq!(tokens("1.0e"), [Token::InvalidNumber("1.0e")]);
+	assert_eq!(
+		tokens("1 abc"),
+		[Token::Integer(1), Token::Identifier("abc")]
+	);
+}
+
+#[test]
+fn paths_need_a_slash() {
+	assert_eq!(tokens("foo/bar"), [Token::Path("foo/bar")]);
+	assert_eq!(tokens("./foo"), [Token::Path("./foo")]);
+	assert_eq!(tokens("foo"), [Token::Identifier("foo")]);
+	assert_eq!(tokens("/"), [Token::Slash]);
+	assert_eq!(
+		tokens("a / b"),
+		[Token::Identifier("a"), Token::Slash, Token::Identifier("b")]
+	);
+	assert_eq!(
+		tokens("a-b/c"),
+		[Token::Identifier("a"), Token::Minus, Token::Path("b/c"),]
+	);
+}
+
+#[test]
+fn a_path_after_a_dot_is_not_an_attribute_name() {
+	assert_eq!(
+		tokens("a.b/c"),
+		[Token::Identifier("a"), Token::Dot, Token::Path("b/c")]
+	);
+	let err = Parser::default().parse("a.b/c", "test.nix").unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"parse error: expected an attribute name (context: select)"
+	);
+}
+
+#[test]
+fn equals_signs_lex_by_longest_match() {
+	let (a, b) = (Token::Identifier("a"), Token::Identifier("b"));
+	assert_eq!(tokens("a == b"), [a.clone(), Token::EqEq, b.clone()]);
+	assert_eq!(tokens("a = b"), [a.clone(), Token::Equals, b.clone()]);
+	assert_eq!(tokens("a==b"), [a.clone(), Token::EqEq, b.clone()]);
+	assert_eq!(tokens("a === b"), [a, Token::EqEq, Token::Equals, b]);
+}
+
+#[test]
+fn dots_lex_as_paths_ellipses_or_selects() {
+	assert_eq!(tokens("../x"), [Token::Path("../x")]);
+	assert_eq!(tokens("./x"), [Token::Path("./x")]);
+	assert_eq!(
+		tokens("{ ... }"),
+		[Token::BraceOpen, Token::Ellipsis, Token::BraceClose]
+	);
+	assert_eq!(
+		tokens("a.b"),
+		[Token::Identifier("a"), Token::Dot, Token::Identifier("b")]
+	);
+	assert_eq!(
+		tokens("a.b.c"),
+		[
+			Token::Identifier("a"),
+			Token::Dot,
+			Token::Identifier("b"),
+			Token::Dot,
+			Token::Identifier("c"),
+		]
+	);
+}
+
+#[test]
+fn digit_separators_follow_rust_rules() {
+	assert_eq!(tokens("1_"), [Token::Integer(1)]);
+	assert_eq!(tokens("1__2"), [Token::Integer(12)]);
+	assert_eq!(tokens("1_000"), [Token::Integer(1000)]);
+	assert_eq!(tokens("0x_ff"), [Token::HexInteger(255)]);
+	// A leading underscore makes a name.
+	assert_eq!(tokens("_1"), [Token::Identifier("_1")]);
+}
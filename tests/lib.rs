@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use nixc::{nix_value, parse_and_merge, parser::Parser, render_diagnostic};
+
+#[test]
+fn render_diagnostic_renders_a_parse_error() {
+	let err = Parser::default()
+		.parse("{ a = 1, b = 2 }", "broken.nix")
+		.unwrap_err();
+	let rendered = render_diagnostic(&*err);
+	assert!(rendered.contains("unexpected ','"), "{rendered}");
+	assert!(rendered.contains("broken.nix"), "{rendered}");
+	// Without colors, there are no escape codes for a UI to strip.
+	assert!(!rendered.contains('\u{1b}'), "{rendered}");
+}
+
+/// Writes `contents` to a file in the temporary directory, named after the
+/// test and this process so that concurrent runs don't clash.
+fn temp_file(name: &str, contents: &str) -> PathBuf {
+	let path = std::env::temp_dir()
+		.join(format!("nixc-{}-{name}", std::process::id()));
+	std::fs::write(&path, contents).expect("temp file should be writable");
+	path
+}
+
+#[test]
+fn parse_and_merge_lets_later_files_override() {
+	let base = temp_file("base.nix", "{ a = 1; b = { c = 2; d = 3; }; }");
+	let overrides = temp_file("overrides.nix", "{ b = { c = 4; }; }");
+	let merged =
+		parse_and_merge(&[base.to_str().unwrap(), overrides.to_str().unwrap()]);
+	std::fs::remove_file(base).unwrap();
+	std::fs::remove_file(overrides).unwrap();
+
+	let expected = nix_value!({ a: 1, b: { c: 4, d: 3 } });
+	assert!(merged.unwrap().approx_eq(&expected, 0.0));
+}
+
+#[test]
+fn parse_and_merge_evaluates_each_file_before_merging() {
+	let base = temp_file("let-base.nix", "{ a = 1; b = { c = 2; }; }");
+	let overrides = temp_file(
+		"let-overrides.nix",
+		"let c = 4; in { b = { inherit c; d = c + 1; }; }",
+	);
+	let merged =
+		parse_and_merge(&[base.to_str().unwrap(), overrides.to_str().unwrap()]);
+	std::fs::remove_file(base).unwrap();
+	std::fs::remove_file(overrides).unwrap();
+
+	let expected = nix_value!({ a: 1, b: { c: 4, d: 5 } });
+	let merged = merged.unwrap();
+	assert!(merged.approx_eq(&expected, 0.0), "{merged:?}");
+}
+
+#[test]
+fn parse_and_merge_names_the_file_that_failed() {
+	let good = temp_file("good.nix", "{ a = 1; }");
+	let broken = temp_file("broken.nix", "{ a = ; }");
+	let (good_name, broken_name) =
+		(good.to_str().unwrap(), broken.to_str().unwrap());
+	let err = parse_and_merge(&[good_name, broken_name]).unwrap_err();
+	std::fs::remove_file(&good).unwrap();
+	std::fs::remove_file(&broken).unwrap();
+
+	assert_eq!(err.to_string(), format!("failed to parse {broken_name}"));
+
+	let undefined = temp_file("undefined.nix", "{ a = x; }");
+	let undefined_name = undefined.to_str().unwrap();
+	let err = parse_and_merge(&[undefined_name]).unwrap_err();
+	std::fs::remove_file(&undefined).unwrap();
+	assert_eq!(
+		err.to_string(),
+		format!("failed to evaluate {undefined_name}")
+	);
+
+	let missing = std::env::temp_dir().join("nixc-missing.nix");
+	let missing = missing.to_str().unwrap();
+	let err = parse_and_merge(&[missing]).unwrap_err();
+	assert_eq!(err.to_string(), format!("failed to read {missing}"));
+}
@@ -0,0 +1,1075 @@
+use std::collections::HashMap;
+
+use logos::{Logos, Span};
+use miette::{Report, Severity};
+use nixc::{
+	eval::BinOp,
+	lexer::Token,
+	nix_value,
+	parser::{
+		Comments, KeywordPolicy, Parser, ParserConfig, StringEscapePolicy,
+	},
+	value::{Pattern, Value},
+};
+
+fn parse(src: &str) -> Value<'_> {
+	Parser::default()
+		.parse(src, "test.nix")
+		.expect("should parse")
+}
+
+fn parse_raw(src: &str) -> Value<'_> {
+	let mut parser = Parser::default();
+	parser.config.raw_numbers = true;
+	parser.parse(src, "test.nix").expect("should parse")
+}
+
+fn parse_err(src: &str) -> Report {
+	Parser::default()
+		.parse(src, "test.nix")
+		.expect_err("should fail to parse")
+}
+
+/// The offset and length of the span an error points at.
+fn span(err: &Report) -> (usize, usize) {
+	let label = err.labels().and_then(|mut labels| labels.next());
+	let label = label.expect("error has a label");
+	(label.offset(), label.len())
+}
+
+fn help(err: &Report) -> String {
+	err.help().expect("error has help").to_string()
+}
+
+fn assert_value(actual: &Value, expected: &Value) {
+	assert!(
+		actual.approx_eq(expected, 0.0),
+		"{actual:?} is not {expected:?}"
+	);
+}
+
+#[test]
+fn scalar_bindings_parse_with_or_without_spaces() {
+	assert_value(&parse("{ a = true; }"), &nix_value!({ a: true }));
+	assert_value(
+		&parse("{ a = false; b = null; }"),
+		&nix_value!({ a: false, b: null }),
+	);
+	assert_value(
+		&parse("{ a=true;b=false; }"),
+		&nix_value!({ a: true, b: false }),
+	);
+}
+
+#[test]
+fn attrset_syntax_in_a_list_suggests_braces() {
+	let err = parse_err("[ a = 1; ]");
+	assert_eq!(
+		err.to_string(),
+		"parse error: unexpected attribute binding (context: list)"
+	);
+	assert_eq!(span(&err), (2, 3));
+	assert!(help(&err).contains("written with braces"));
+}
+
+#[test]
+fn list_syntax_in_an_attrset_suggests_brackets() {
+	let err = parse_err("{ 1 2 }");
+	assert_eq!(
+		err.to_string(),
+		"parse error: expected an attribute name, found a value (context: \
+		 attrset)"
+	);
+	assert_eq!(span(&err), (2, 1));
+	assert!(help(&err).contains("written with brackets"));
+}
+
+fn lex(src: &str) -> Vec<(Token<'_>, Span)> {
+	Token::lexer(src)
+		.spanned()
+		.map(|(token, span)| (token.expect("lexes"), span))
+		.collect()
+}
+
+#[test]
+fn parsing_pre_lexed_tokens_matches_parsing_the_source() {
+	let src = "{ a = [ 1 2.5 ]; b = { c = \"x\"; }; d = 1 + 2; }";
+	let tokens = lex(src);
+
+	let from_tokens = Parser::default()
+		.parse_value_from_tokens(src, &tokens)
+		.unwrap();
+	assert_value(&from_tokens, &parse(src));
+	assert_value(
+		&from_tokens,
+		&nix_value!({ a: [1, 2.5], b: { c: "x" }, d: 3 }),
+	);
+}
+
+#[test]
+fn numbers_running_into_names_are_errors() {
+	let err = parse_err("1abc");
+	assert_eq!(
+		err.to_string(),
+		"parse error: invalid number literal `1abc`"
+	);
+	assert_eq!(span(&err), (0, 4));
+
+	let err = parse_err("0x1g");
+	assert_eq!(
+		err.to_string(),
+		"parse error: invalid number literal `0x1g`"
+	);
+
+	let err = parse_err("1.0e");
+	assert_eq!(
+		err.to_string(),
+		"parse error: incomplete exponent in number literal `1.0e`"
+	);
+}
+
+#[test]
+fn one_parser_parses_several_sources() {
+	let mut parser = Parser::default();
+	let first = parser.parse("{ a = 1; }", "first.nix").unwrap();
+	let second = parser.parse("[ true null ]", "second.nix").unwrap();
+	assert_value(&first, &nix_value!({ a: 1 }));
+	assert_value(&second, &nix_value!([true, null]));
+
+	let err = parser.parse("{ a = ; }", "third.nix").unwrap_err();
+	assert!(err.to_string().starts_with("parse error"));
+	assert_value(
+		&parser.parse("\"x\"", "fourth.nix").unwrap(),
+		&nix_value!("x"),
+	);
+}
+
+#[test]
+fn the_body_of_with_extends_to_the_right() {
+	let value = parse("with s; 1 + 2");
+	let Value::With(scope, body) = &value else {
+		panic!("expected a with, got {value:?}");
+	};
+	assert!(matches!(&**scope, Value::Ref(name) if name == "s"));
+	assert!(matches!(**body, Value::Integer(3)));
+
+	let value = parse("with s; a + f b");
+	let Value::With(_, body) = &value else {
+		panic!("expected a with, got {value:?}");
+	};
+	let Value::BinOp(_, lhs, rhs) = &**body else {
+		panic!("expected an addition, got {body:?}");
+	};
+	assert!(matches!(&**lhs, Value::Ref(name) if name == "a"));
+	assert!(matches!(**rhs, Value::Apply(..)));
+}
+
+#[test]
+fn lenient_mode_accepts_a_missing_final_semicolon() {
+	let mut parser = Parser::default();
+	parser.config.lenient = true;
+	let value = parser.parse("{ a = 1 }", "test.nix").unwrap();
+	assert_value(&value, &nix_value!({ a: 1 }));
+	assert_value(
+		&parser.parse("{ a = 1; b = 2 }", "test.nix").unwrap(),
+		&nix_value!({ a: 1, b: 2 }),
+	);
+	// Bindings still need separating.
+	assert!(parser.parse("{ a = 1 b = 2 }", "test.nix").is_err());
+}
+
+#[test]
+fn strict_mode_requires_the_final_semicolon() {
+	let err = parse_err("{ a = 1 }");
+	assert_eq!(
+		err.to_string(),
+		"parse error: expected ';' (context: attrset)"
+	);
+	assert_eq!(span(&err), (8, 1));
+}
+
+#[test]
+fn rec_names_an_attribute_unless_it_precedes_a_brace() {
+	assert_value(&parse("{ rec = 1; }"), &nix_value!({ rec: 1 }));
+	assert!(
+		matches!(parse("rec { }"), Value::RecAttrSet(map) if map.is_empty())
+	);
+
+	let value = parse("rec { a = 1; b = a; }");
+	let Value::RecAttrSet(map) = &value else {
+		panic!("expected a rec attrset, got {value:?}");
+	};
+	assert!(matches!(map.get("b"), Some(Value::Ref(name)) if name == "a"));
+}
+
+#[test]
+fn a_long_select_chain_parses_without_recursing() {
+	let names = (0..100_000).map(|i| format!("a{i}")).collect::<Vec<_>>();
+	let src = format!("x.{}", names.join("."));
+	let value = parse(&src);
+	let Value::Select(base, path, None) = &value else {
+		panic!("expected a select");
+	};
+	assert!(matches!(&**base, Value::Ref(name) if name == "x"));
+	assert_eq!(path.len(), names.len());
+	assert_eq!(path.last().unwrap(), "a99999");
+}
+
+#[test]
+fn operators_may_span_lines() {
+	assert!(matches!(parse("1 +\n2"), Value::Integer(3)));
+	assert_value(&parse("[ 1 ]\n++ [ 2 ]\n"), &nix_value!([1, 2]));
+	assert_value(
+		&parse("{\n  a = [ 1 ]\n    ++ [ 2 ];\n  b = 1 +\n    2;\n}"),
+		&nix_value!({ a: [1, 2], b: 3 }),
+	);
+}
+
+#[test]
+fn a_misplaced_at_gets_a_targeted_error() {
+	let err = parse_err("foo@bar");
+	assert_eq!(err.to_string(), "parse error: unexpected '@'");
+	assert_eq!(span(&err), (3, 1));
+	assert!(help(&err).contains("args@{ a, ... }: a"));
+
+	let err = parse_err("{ a = @; }");
+	assert_eq!(err.to_string(), "parse error: unexpected '@'");
+	assert_eq!(span(&err), (6, 1));
+
+	// It's fine binding the argument of a function.
+	assert!(matches!(parse("args@{ a }: a"), Value::Lambda(..)));
+}
+
+#[test]
+fn raw_numbers_keep_literals_as_written() {
+	let value = parse_raw("0xFF");
+	assert!(matches!(&value, Value::RawNumber(s) if s == "0xFF"));
+	assert_eq!(value.to_nix(), "0xFF");
+
+	let value = parse_raw("{ a = 1_000; b = 1.50e3; }");
+	assert_eq!(value.to_nix(), "{\n  a = 1_000;\n  b = 1.50e3;\n}");
+	// Without the mode, only the value is kept.
+	assert!(matches!(parse("0xFF"), Value::Integer(255)));
+}
+
+#[test]
+fn tokens_over_the_length_limit_are_rejected() {
+	let mut parser = Parser::default();
+	parser.config.max_token_length = 8;
+	assert!(parser.parse("{ abcdefgh = 1; }", "test.nix").is_ok());
+
+	let err = parser.parse("{ a = abcdefghi; }", "test.nix").unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"parse error: token is 9 bytes long, more than the limit of 8"
+	);
+	assert_eq!(span(&err), (6, 0));
+}
+
+#[test]
+fn the_default_length_limit_is_generous_but_bounded() {
+	assert_eq!(ParserConfig::default().max_token_length, 4 * 1024);
+	assert!(Parser::default()
+		.parse(&"a".repeat(4 * 1024), "test.nix")
+		.is_ok());
+}
+
+#[test]
+fn tokens_just_over_the_default_limit_are_rejected() {
+	let long = 4 * 1024 + 1;
+	let tokens = [
+		"a".repeat(long),
+		"1".repeat(long),
+		format!("1.{}", "5".repeat(long - 2)),
+		format!("\"{}\"", "a".repeat(long - 2)),
+	];
+	for token in tokens {
+		let err = Parser::default()
+			.parse(&format!("{{ a = {token}; }}"), "test.nix")
+			.unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"parse error: token is 4097 bytes long, more than the limit of 4096"
+		);
+		assert_eq!(span(&err), (6, 0));
+	}
+}
+
+#[test]
+fn huge_tokens_are_rejected_by_every_entry_point_without_crashing() {
+	let src = format!("{{ a = {}; }}", "a".repeat(1 << 20));
+	let mut parser = Parser::default();
+	assert!(parser.parse(&src, "test.nix").is_err());
+	assert!(parser.parse_value(&mut Token::lexer(&src)).is_err());
+
+	let mut stream = parser.attr_stream(Token::lexer(&src));
+	let err = stream.next().unwrap().unwrap_err();
+	assert!(err.to_string().contains("more than the limit of 4096"));
+}
+
+#[test]
+fn every_entry_point_checks_token_lengths() {
+	let src = "{ a = abcdefghi; }";
+	let mut parser = Parser::default();
+	parser.config.max_token_length = 8;
+
+	let tokens = Token::lexer(src)
+		.spanned()
+		.map(|(token, span)| (token.unwrap(), span))
+		.collect::<Vec<_>>();
+	assert!(parser.parse_value_from_tokens(src, &tokens).is_err());
+	assert!(parser.parse_value(&mut Token::lexer(src)).is_err());
+
+	let mut stream = parser.attr_stream(Token::lexer(src));
+	let err = stream.next().unwrap().unwrap_err();
+	assert!(err.to_string().contains("more than the limit of 8"));
+	assert!(stream.next().is_none());
+}
+
+#[test]
+fn attr_streams_check_token_lengths_as_they_reach_them() {
+	let mut parser = Parser::default();
+	parser.config.max_token_length = 8;
+	let mut stream =
+		parser.attr_stream(Token::lexer("{ a = 1; b = abcdefghi; }"));
+	let (name, _) = stream.next().unwrap().unwrap();
+	assert_eq!(name, "a");
+	let err = stream.next().unwrap().unwrap_err();
+	assert!(err.to_string().contains("more than the limit of 8"));
+	assert_eq!(span(&err), (13, 0));
+	assert!(stream.next().is_none());
+
+	// A consumer that stops early never reaches the long token.
+	let src = format!("{{ a = 1; b = {}; }}", "a".repeat(1 << 20));
+	let mut parser = Parser::default();
+	let mut stream = parser.attr_stream(Token::lexer(&src));
+	assert!(stream.next().unwrap().is_ok());
+}
+
+#[test]
+fn eof_after_equals_points_at_the_equals() {
+	let err = parse_err("{ a =");
+	assert_eq!(err.to_string(), "parse error: expected a value after '='");
+	assert_eq!(span(&err), (4, 1));
+	assert_eq!(span(&parse_err("{ a =  \n")), (4, 1));
+}
+
+#[test]
+fn select_with_default_is_one_list_element() {
+	let with_default = Value::Select(
+		Box::new(Value::Ref("a".into())),
+		vec!["b".into()],
+		Some(Box::new(Value::Integer(0))),
+	);
+	assert_value(
+		&parse("[ (a.b or 0) ]"),
+		&Value::List(vec![with_default.clone()]),
+	);
+	assert_value(
+		&parse("[ a.b or 0 1 ]"),
+		&Value::List(vec![with_default, Value::Integer(1)]),
+	);
+}
+
+#[test]
+fn trailing_and_leading_dots_make_floats() {
+	assert!(matches!(parse("1."), Value::Float(n) if n == 1.0));
+	assert!(matches!(parse(".1"), Value::Float(n) if n == 0.1));
+	assert!(matches!(parse("[ 1. ]"), Value::List(items)
+		if matches!(items[..], [Value::Float(n)] if n == 1.0)));
+}
+
+#[test]
+fn integer_followed_by_a_name_is_a_select() {
+	assert!(matches!(
+		parse("1.foo"),
+		Value::Select(base, path, None)
+			if matches!(*base, Value::Integer(1)) && path == ["foo"]
+	));
+}
+
+#[test]
+fn hex_integers_keep_their_case() {
+	assert!(matches!(parse("0xFF"), Value::Integer(255)));
+	assert!(matches!(parse("0xff"), Value::Integer(255)));
+
+	for literal in ["0xFF", "0xff"] {
+		let value = parse_raw(literal);
+		assert_eq!(value.to_nix(), literal);
+		assert!(matches!(value.canonicalize(), Ok(Value::Integer(255))));
+	}
+}
+
+#[test]
+fn stray_semicolon_in_an_attrset_is_reported() {
+	let message =
+		"parse error: unexpected ';' — expected an attribute name or '}'";
+	let err = parse_err("{ ; }");
+	assert_eq!(err.to_string(), message);
+	assert_eq!(span(&err), (2, 1));
+
+	let err = parse_err("{ a = 1; ; }");
+	assert_eq!(err.to_string(), message);
+	assert_eq!(span(&err), (9, 1));
+}
+
+#[test]
+fn attr_stream_yields_bindings_in_order() {
+	let src = "{ a = 1; b = { c = true; }; d = [ ]; e = \"x\"; }";
+	let mut parser = Parser::default();
+	let first_two = parser
+		.attr_stream(Token::lexer(src))
+		.take(2)
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+	assert_eq!(first_two.len(), 2);
+	assert_eq!(first_two[0].0, "a");
+	assert_value(&first_two[0].1, &nix_value!(1));
+	assert_eq!(first_two[1].0, "b");
+	assert_value(&first_two[1].1, &nix_value!({ c: true }));
+
+	let all = parser.attr_stream(Token::lexer(src)).count();
+	assert_eq!(all, 4);
+}
+
+#[test]
+fn attr_stream_stops_before_later_errors() {
+	let src = "{ a = 1; b = 2; c = ; }";
+	let mut parser = Parser::default();
+	let mut stream = parser.attr_stream(Token::lexer(src));
+	assert!(stream.next().unwrap().is_ok());
+	assert!(stream.next().unwrap().is_ok());
+	assert!(stream.next().unwrap().is_err());
+}
+
+#[test]
+fn unknown_escapes_follow_the_escape_policy() {
+	let src = r#""a\qb""#;
+	assert_eq!(ParserConfig::default().escapes, StringEscapePolicy::Strict);
+	let err = parse_err(src);
+	assert_eq!(
+		err.to_string(),
+		"parse error: unknown escape sequence `\\q`"
+	);
+	assert_eq!(span(&err), (2, 2));
+
+	let mut parser = Parser::default();
+	parser.config.escapes = StringEscapePolicy::Passthrough;
+	let value = parser.parse(src, "test.nix").unwrap();
+	assert_value(&value, &nix_value!("a\\qb"));
+	// Known escapes are still decoded.
+	let value = parser.parse(r#""\q\n""#, "test.nix").unwrap();
+	assert_value(&value, &nix_value!("\\q\n"));
+}
+
+#[test]
+fn nested_parentheses_group_operations() {
+	assert!(matches!(parse("(((1)))"), Value::Integer(1)));
+	assert!(matches!(parse("((1 + 2) * (3 - 4))"), Value::Integer(-3)));
+	// Without constants to fold, the grouping stays visible.
+	let value = parse("((a + b) * (c - d))");
+	assert_eq!(value.to_nix(), "(a + b) * (c - d)");
+}
+
+#[test]
+fn unbalanced_parentheses_point_at_the_unmatched_one() {
+	let err = parse_err("((1)");
+	assert_eq!(
+		err.to_string(),
+		"parse error: unmatched opening parenthesis"
+	);
+	assert_eq!(span(&err), (0, 1));
+
+	let err = parse_err("(1))");
+	assert_eq!(
+		err.to_string(),
+		"parse error: unmatched closing parenthesis"
+	);
+	assert_eq!(span(&err), (3, 1));
+}
+
+#[test]
+fn if_expressions_end_before_the_binding_or_element_ends() {
+	let cond = || {
+		Value::If(
+			Box::new(Value::Ref("c".into())),
+			Box::new(Value::Integer(1)),
+			Box::new(Value::Integer(2)),
+		)
+	};
+	let mut expected = nixc::value::Map::new();
+	expected.insert("x".into(), cond());
+	expected.insert("y".into(), Value::Integer(3));
+	assert_value(
+		&parse("{ x = if c then 1 else 2; y = 3; }"),
+		&Value::AttrSet(expected),
+	);
+	assert_value(
+		&parse("[ (if c then 1 else 2) 3 ]"),
+		&Value::List(vec![cond(), Value::Integer(3)]),
+	);
+}
+
+#[test]
+fn errors_deep_in_a_document_carry_a_breadcrumb() {
+	let err = parse_err("{ a = [ { b = let x = ; in x; } ]; }");
+	assert_eq!(span(&err), (22, 1));
+	assert!(
+		help(&err).ends_with(
+			"in attribute 'a' → in list → in attribute 'b' → in let binding \
+			 'x'"
+		),
+		"{}",
+		help(&err)
+	);
+
+	// The context of an earlier parse doesn't leak into the next one.
+	let mut parser = Parser::default();
+	parser.parse("{ a = [ ( ]; }", "test.nix").unwrap_err();
+	let err = parser.parse("{ b = ; }", "test.nix").unwrap_err();
+	assert!(help(&err).ends_with("in attribute 'b'"), "{}", help(&err));
+}
+
+#[test]
+fn let_without_bindings_is_an_error() {
+	let err = parse_err("let in 1");
+	assert_eq!(
+		err.to_string(),
+		"parse error: let expression requires at least one binding"
+	);
+	assert_eq!(span(&err), (0, 6));
+
+	let Value::LetIn(bindings, body) = parse("let x = 1; in x") else {
+		panic!("expected a let");
+	};
+	assert_value(&Value::AttrSet(bindings), &nix_value!({ x: 1 }));
+	assert!(matches!(*body, Value::Ref(name) if name == "x"));
+}
+
+#[test]
+fn commas_between_attributes_suggest_semicolons() {
+	let err = parse_err("{ a = 1, b = 2 }");
+	assert_eq!(
+		err.to_string(),
+		"parse error: unexpected ',' — Nix uses ';' to separate attributes"
+	);
+	assert_eq!(span(&err), (7, 1));
+}
+
+#[test]
+fn validator_errors_point_at_the_rejected_value() {
+	let mut parser = Parser::default();
+	parser.validate_with(|value, _| match value {
+		Value::Integer(n) if *n < 0 => Err(format!("{n} is negative")),
+		_ => Ok(()),
+	});
+	let src = "{ a = 1; b = [ 2 (-3) ]; }";
+	let err = parser.parse(src, "test.nix").unwrap_err();
+	assert_eq!(err.to_string(), "parse error: -3 is negative");
+	assert_eq!(span(&err), (18, 2));
+
+	assert!(parser.parse("{ a = [ 1 2 ]; }", "test.nix").is_ok());
+}
+
+#[test]
+fn exponents_need_digits() {
+	let err = parse_err("1e");
+	assert_eq!(
+		err.to_string(),
+		"parse error: incomplete exponent in number literal `1e`"
+	);
+	assert_eq!(span(&err), (0, 2));
+	let err = parse_err("1e+");
+	assert_eq!(
+		err.to_string(),
+		"parse error: incomplete exponent in number literal `1e+`"
+	);
+	assert_eq!(span(&err), (0, 3));
+
+	assert!(matches!(parse("1e3"), Value::Float(n) if n == 1000.0));
+}
+
+#[test]
+fn recovery_keeps_later_spans_accurate() {
+	let src = r#"{ a = { x = ; }; b = "s"; c = ]; d = 1; }"#;
+	let errors = Parser::default()
+		.parse_with_recovery(src, "test.nix")
+		.unwrap_err();
+	assert_eq!(errors.len(), 2);
+	assert_eq!(
+		errors[0].to_string(),
+		"parse error: unexpected ';'; did you forget a value?"
+	);
+	assert_eq!(span(&errors[0]), (12, 1));
+	assert_eq!(
+		errors[1].to_string(),
+		"parse error: unexpected token (context: value)"
+	);
+	assert_eq!(span(&errors[1]), (src.find(']').unwrap(), 1));
+}
+
+#[test]
+fn recovery_resumes_after_the_binding_that_failed() {
+	let messages = |src| {
+		Parser::default()
+			.parse_with_recovery(src, "test.nix")
+			.unwrap_err()
+			.iter()
+			.map(|err| (err.to_string(), span(err)))
+			.collect::<Vec<_>>()
+	};
+
+	// The `;` ending `a` was taken as the missing value, so `b` is next.
+	assert_eq!(
+		messages("{ a = ; b = ]; c = 1; }"),
+		[
+			(
+				"parse error: unexpected ';'; did you forget a value?".into(),
+				(6, 1)
+			),
+			(
+				"parse error: unexpected token (context: value)".into(),
+				(12, 1)
+			),
+		]
+	);
+	// The `;` after the error is inside the braces it's nested in.
+	assert_eq!(
+		messages("{ a = { inherit 1; c = 2; }; d = ]; }"),
+		[
+			(
+				"parse error: expected a name or ';' (context: inherit)".into(),
+				(16, 1)
+			),
+			(
+				"parse error: unexpected token (context: value)".into(),
+				(33, 1)
+			),
+		]
+	);
+	// Neither does the `;` of a `with` or the bindings of a `let`.
+	assert_eq!(
+		messages("{ a = with s; ); b = let x = 1; in ); c = ]; }"),
+		[
+			(
+				"parse error: unexpected token (context: value)".into(),
+				(14, 1)
+			),
+			(
+				"parse error: unexpected token (context: value)".into(),
+				(35, 1)
+			),
+			(
+				"parse error: unexpected token (context: value)".into(),
+				(42, 1)
+			),
+		]
+	);
+}
+
+#[test]
+fn unexpected_tokens_are_pointed_at() {
+	assert_eq!(span(&parse_err(r#"{ b = "s"; c = ]; }"#)), (15, 1));
+	assert_eq!(span(&parse_err("[ 1 ( ]")), (6, 1));
+}
+
+#[test]
+fn bare_scalars_parse_with_a_trailing_newline() {
+	assert!(matches!(parse("42\n"), Value::Integer(42)));
+	assert!(matches!(parse("true\n"), Value::Bool(true)));
+	assert!(matches!(parse("\"x\"\n"), Value::String(s) if s == "x"));
+	assert!(matches!(parse("null\n"), Value::Null));
+	assert!(matches!(parse("  42 \n\n"), Value::Integer(42)));
+
+	let err = parse_err("42\n43\n");
+	assert_eq!(
+		err.to_string(),
+		"parse error: unexpected token after the top-level value"
+	);
+}
+
+#[test]
+fn braces_start_a_lambda_or_an_attrset() {
+	let Value::Lambda(
+		Pattern::Set {
+			params,
+			ellipsis,
+			bind,
+		},
+		body,
+	) = parse("{ a, b }: a")
+	else {
+		panic!("expected a lambda");
+	};
+	let names = params.iter().map(|(name, _)| &**name).collect::<Vec<_>>();
+	assert_eq!(names, ["a", "b"]);
+	assert!(params.iter().all(|(_, default)| default.is_none()));
+	assert!(!ellipsis && bind.is_none());
+	assert!(matches!(*body, Value::Ref(name) if name == "a"));
+
+	assert_value(&parse("{ a = 1; }"), &nix_value!({ a: 1 }));
+	assert!(matches!(parse("{ }: 1"), Value::Lambda(..)));
+	assert_value(&parse("{ }"), &nix_value!({}));
+	assert!(matches!(parse("{ a }: a"), Value::Lambda(..)));
+}
+
+fn comments_of(src: &str) -> HashMap<Vec<String>, Comments> {
+	let mut parser = Parser::default();
+	parser.config.keep_comments = true;
+	parser.parse(src, "test.nix").expect("should parse");
+	parser.comments().clone()
+}
+
+fn path(names: &[&str]) -> Vec<String> {
+	names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn trailing_comments_belong_to_their_line() {
+	let comments = comments_of(
+		"{\n  # about a\n  a = 1; # the a value\n  b = 2;\n  c = {\n    d = \
+		 3; # the d value\n  };\n}\n",
+	);
+	let a = &comments[&path(&["a"])];
+	assert_eq!(a.leading, ["about a"]);
+	assert_eq!(a.trailing.as_deref(), Some("the a value"));
+	assert!(!comments.contains_key(&path(&["b"])));
+	let d = &comments[&path(&["c", "d"])];
+	assert_eq!(d.trailing.as_deref(), Some("the d value"));
+	assert!(d.leading.is_empty());
+}
+
+#[test]
+fn keywords_as_names_follow_the_keyword_policy() {
+	assert_eq!(ParserConfig::default().keywords, KeywordPolicy::Reserved);
+	let err = parse_err("{ if = 1; }");
+	assert_eq!(
+		err.to_string(),
+		"parse error: `if` is a keyword, so it can't name an attribute \
+		 (context: attrset)"
+	);
+	assert_eq!(span(&err), (2, 2));
+	assert_eq!(span(&parse_err("{ let = 1; }")), (2, 3));
+	// These are only keywords where they're used as such.
+	assert_value(
+		&parse("{ rec = 1; or = 2; }"),
+		&nix_value!({ rec: 1, or: 2 }),
+	);
+
+	let mut parser = Parser::default();
+	parser.config.keywords = KeywordPolicy::AsNames;
+	let value = parser.parse("{ if = 1; let = 2; with = 3; }", "t").unwrap();
+	assert_value(&value, &nix_value!({ if: 1, let: 2, with: 3 }));
+}
+
+#[test]
+fn underscores_in_exponents_are_ignored() {
+	assert!(matches!(parse("1e1_0"), Value::Float(n) if n == 1e10));
+	assert!(matches!(parse("1.5e1_0"), Value::Float(n) if n == 1.5e10));
+	assert!(matches!(parse("1_0e1"), Value::Float(n) if n == 100.0));
+}
+
+#[test]
+fn nested_attrsets_parse_with_or_without_spaces() {
+	let expected = nix_value!({ a: { b: 1 }, c: 2 });
+	assert_value(&parse("{ a = { b = 1; }; c = 2; }"), &expected);
+	assert_value(&parse("{a={b=1;};c=2;}"), &expected);
+	assert_value(
+		&parse("{\n\ta = {\n\t\tb = 1;\n\t};\n\tc = 2;\n}"),
+		&expected,
+	);
+
+	let deep = nix_value!({ a: { b: { c: 1 } } });
+	assert_value(&parse("{ a = { b = { c = 1; }; }; }"), &deep);
+	assert_value(&parse("{a={b={c=1;};};}"), &deep);
+}
+
+#[test]
+fn nested_attrset_needs_the_outer_semicolon() {
+	let err = parse_err("{ a = { b = 1; } }");
+	assert_eq!(
+		err.to_string(),
+		"parse error: expected ';' (context: attrset)"
+	);
+}
+
+/// The offset and length of every span an error points at.
+fn spans(err: &Report) -> Vec<(usize, usize)> {
+	let labels = err.labels().expect("error has labels");
+	labels.map(|label| (label.offset(), label.len())).collect()
+}
+
+#[test]
+fn mismatched_delimiters_point_at_both_ends() {
+	let err = parse_err("[ 1 }");
+	assert_eq!(
+		err.to_string(),
+		"parse error: mismatched delimiter: expected ']' to close '[' but \
+		 found '}'"
+	);
+	assert_eq!(spans(&err), [(0, 1), (4, 1)]);
+
+	let err = parse_err("{ a = [ 1 ); }");
+	assert_eq!(
+		err.to_string(),
+		"parse error: mismatched delimiter: expected ']' to close '[' but \
+		 found ')'"
+	);
+	assert_eq!(spans(&err), [(6, 1), (10, 1)]);
+
+	let err = parse_err("{ a = 1; ]");
+	assert_eq!(spans(&err), [(0, 1), (9, 1)]);
+}
+
+#[test]
+fn empty_parentheses_are_an_error() {
+	let err = parse_err("()");
+	assert_eq!(
+		err.to_string(),
+		"parse error: empty parentheses are not a valid expression"
+	);
+	assert_eq!(span(&err), (0, 2));
+	assert_eq!(span(&parse_err("[ ( ) ]")), (2, 3));
+}
+
+#[test]
+fn updates_chain_with_the_rightmost_winning() {
+	assert_value(
+		&parse("{ a = 1; b = 1; } // { a = 2; c = 2; } // { a = 3; }"),
+		&nix_value!({ a: 3, b: 1, c: 2 }),
+	);
+
+	let Value::BinOp(BinOp::Update, lhs, rhs) = parse("x // y // z") else {
+		panic!("expected an update");
+	};
+	assert!(matches!(*lhs, Value::BinOp(BinOp::Update, ..)));
+	assert!(matches!(*rhs, Value::Ref(name) if name == "z"));
+}
+
+#[test]
+fn tab_indentation_is_linted_when_enabled() {
+	let src = "{\n\ta = 1;\n  b = \"\n\tnot indentation\";\n \t c = 2;\n}";
+	let mut parser = Parser::default();
+	parser.config.lint_tabs = true;
+	parser.parse(src, "test.nix").unwrap();
+	let warnings = parser.warnings();
+	assert_eq!(warnings.len(), 2, "{warnings:?}");
+	assert_eq!(warnings[0].to_string(), "line is indented with a tab");
+	assert_eq!(warnings[0].severity(), Some(Severity::Warning));
+	assert_eq!(span(&warnings[0]), (2, 1));
+	assert_eq!(span(&warnings[1]), (src.find(" \t c").unwrap(), 3));
+
+	let spaces = "{\n  a = 1;\n  b = 2;\n}";
+	parser.parse(spaces, "test.nix").unwrap();
+	assert!(parser.warnings().is_empty());
+
+	// The lint is off by default.
+	let mut parser = Parser::default();
+	parser.parse(src, "test.nix").unwrap();
+	assert!(parser.warnings().is_empty());
+}
+
+#[test]
+fn trailing_identifiers_are_reported_by_name() {
+	let err = parse_err("1 extra");
+	assert_eq!(
+		err.to_string(),
+		"parse error: unexpected identifier `extra` after the top-level \
+		 expression"
+	);
+	assert_eq!(span(&err), (2, 5));
+	let err = parse_err("{} foo");
+	assert_eq!(
+		err.to_string(),
+		"parse error: unexpected identifier `foo` after the top-level \
+		 expression"
+	);
+	assert_eq!(span(&err), (3, 3));
+
+	let err = parse_err("1 ]");
+	assert_eq!(err.to_string(), "parse error: unmatched closing bracket");
+	assert_eq!(span(&err), (2, 1));
+}
+
+#[test]
+fn escaped_interpolation_in_an_indented_string_is_literal() {
+	assert_value(&parse("''''${x}''"), &nix_value!("${x}"));
+	assert_value(&parse("''a ''${x} b''"), &nix_value!("a ${x} b"));
+	// Unlike in a double-quoted string, a backslash doesn't escape it.
+	let err = parse_err(r"''\${x}''");
+	assert_eq!(
+		err.to_string(),
+		"parse error: interpolation in indented strings isn't supported"
+	);
+	assert_eq!(span(&err), (3, 2));
+}
+
+#[test]
+fn true_false_and_null_can_name_attributes() {
+	let value = parse("{ true = 1; false = 2; null = 3; }");
+	assert_value(&value, &nix_value!({ "true": 1, "false": 2, "null": 3 }));
+	assert_eq!(
+		value.to_nix(),
+		"{\n  false = 2;\n  null = 3;\n  true = 1;\n}"
+	);
+	// They're still values where a value is expected.
+	assert_value(
+		&parse("{ a = true; null = null; }"),
+		&nix_value!({ a: true, "null": null }),
+	);
+}
+
+#[test]
+fn only_inherited_bindings_are_written_as_inherit() {
+	assert_eq!(parse("{ inherit x; }").to_nix(), "{\n  inherit x;\n}");
+	assert_eq!(parse("{ x = x; }").to_nix(), "{\n  x = x;\n}");
+}
+
+#[test]
+fn large_attrsets_parse_completely() {
+	let mut src = String::from("{\n");
+	for i in 0..10_000 {
+		src.push_str(&format!("  key{i} = {{ value = {i}; }};\n"));
+	}
+	src.push('}');
+	let value = parse(&src);
+	let Value::AttrSet(map) = &value else {
+		panic!("expected an attrset");
+	};
+	assert_eq!(map.len(), 10_000);
+	assert!(matches!(value.query("key0.value"), Some(Value::Integer(0))));
+	assert!(matches!(
+		value.query("key9999.value"),
+		Some(Value::Integer(9999))
+	));
+}
+
+#[test]
+fn later_bindings_of_a_name_replace_earlier_ones() {
+	assert_value(
+		&parse("{ a = 1; b = 2; a = 3; }"),
+		&nix_value!({ a: 3, b: 2 }),
+	);
+}
+
+#[test]
+fn blank_lines_before_attributes_are_recorded() {
+	let comments = comments_of(
+		"{\n  a = 1;\n\n  b = 2;\n\n\n\n  c = 3;\n  d = 4;\n  # detached\n\n  \
+		 e = 5;\n}\n",
+	);
+	let blank = |name| comments[&path(&[name])].blank_line_before;
+	assert!(blank("b"));
+	// Any number of blank lines counts as one.
+	assert!(blank("c"));
+	assert!(!comments.contains_key(&path(&["a"])));
+	assert!(!comments.contains_key(&path(&["d"])));
+	// A comment separated by a blank line doesn't lead the attribute.
+	let e = &comments[&path(&["e"])];
+	assert!(e.blank_line_before);
+	assert!(e.leading.is_empty(), "{e:?}");
+}
+
+#[test]
+fn arrows_suggest_a_colon() {
+	let err = parse_err("x => y");
+	assert_eq!(err.to_string(), "parse error: Nix lambdas use ':' not '=>'");
+	assert_eq!(span(&err), (2, 2));
+	assert_eq!(span(&parse_err("{ f = x => x; }")), (8, 2));
+}
+
+/// Asserts that the printed value parses back to the same value.
+fn assert_round_trips(src: &str) -> String {
+	let value = parse(src);
+	let printed = value.to_nix();
+	let reparsed = Parser::default()
+		.parse(&printed, "printed.nix")
+		.unwrap_or_else(|err| panic!("{printed} doesn't parse: {err}"));
+	assert_value(&reparsed, &value);
+	printed
+}
+
+#[test]
+fn operators_round_trip_through_to_nix() {
+	let printed = assert_round_trips(
+		"{ x = a ++ b; y = c // d; z = (a + b) * c - d / e; w = (a ++ b) \
+		 ++ c; v = a ++ (b ++ c); u = (c // d) // e; t = a == (b == c); }",
+	);
+	assert_eq!(
+		printed,
+		"{\n  t = a == (b == c);\n  u = c // d // e;\n  v = a ++ b ++ c;\n  w \
+		 = (a ++ b) ++ c;\n  x = a ++ b;\n  y = c // d;\n  z = (a + b) * c - d \
+		 / e;\n}"
+	);
+}
+
+#[test]
+fn operations_on_literals_are_written_folded() {
+	let printed = assert_round_trips(
+		"{ x = 1 + 2; y = a + 2 * 3; z = [ 1 ] ++ [ 2 ]; w = (a + 1) + 2; }",
+	);
+	assert_eq!(
+		printed,
+		"{\n  w = a + 1 + 2;\n  x = 3;\n  y = a + 6;\n  z = [ 1 2 ];\n}"
+	);
+}
+
+#[test]
+fn selects_from_paths_keep_their_parentheses() {
+	assert_eq!(assert_round_trips("(./x).foo"), "(./x).foo");
+	assert_eq!(assert_round_trips("x: (./a).b or 1"), "x: (./a).b or 1");
+}
+
+#[test]
+fn stray_equals_and_semicolons_get_targeted_messages() {
+	let err = parse_err("=");
+	assert_eq!(
+		err.to_string(),
+		"parse error: unexpected '='; did you forget an attribute name?"
+	);
+	assert_eq!(span(&err), (0, 1));
+	assert!(help(&err).contains("`name = value;`"), "{}", help(&err));
+
+	let err = parse_err(";");
+	assert_eq!(
+		err.to_string(),
+		"parse error: unexpected ';'; did you forget a value?"
+	);
+	assert_eq!(span(&err), (0, 1));
+
+	assert_eq!(span(&parse_err("[ 1 = ]")), (4, 1));
+}
+
+#[test]
+fn pre_lexed_tokens_report_errors_at_the_same_spans() {
+	let sources = [
+		"1 2",
+		"{ } foo",
+		"[ 1 ]  ]",
+		"[ 1 ",
+		"{ a",
+		"{ a ",
+		"{ a = [ 1 2 ]",
+		"let d = 1",
+		"if a",
+		"1 +",
+	];
+	for src in sources {
+		let tokens = lex(src);
+		let from_tokens = Parser::default()
+			.parse_value_from_tokens(src, &tokens)
+			.expect_err("should fail to parse");
+		let from_source = parse_err(src);
+		assert_eq!(from_tokens.to_string(), from_source.to_string(), "{src}");
+		assert_eq!(spans(&from_tokens), spans(&from_source), "{src}");
+	}
+
+	let err = Parser::default()
+		.parse_value_from_tokens("1 2", &lex("1 2"))
+		.unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"parse error: unexpected token after the top-level value"
+	);
+	assert_eq!(span(&err), (2, 1));
+}
@@ -0,0 +1,785 @@
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+};
+
+use nixc::{
+	nix_value,
+	parser::Parser,
+	value::{OwnedValue, Value},
+};
+
+fn assert_value(actual: &Value, expected: &Value) {
+	assert!(
+		actual.approx_eq(expected, 0.0),
+		"{actual:?} is not {expected:?}"
+	);
+}
+
+#[test]
+fn concat_lists_joins_lists_in_order() {
+	let lists = [nix_value!([1, 2]), nix_value!([]), nix_value!([3, "x"])];
+	assert_value(
+		&Value::concat_lists(&lists).unwrap(),
+		&nix_value!([1, 2, 3, "x"]),
+	);
+}
+
+#[test]
+fn concat_lists_rejects_a_non_list() {
+	let lists = [nix_value!([1]), nix_value!(2)];
+	let err = Value::concat_lists(&lists).unwrap_err();
+	assert_eq!(err.to_string(), "expected list, got int");
+}
+
+#[test]
+fn query_follows_attributes_and_list_indices() {
+	let value = nix_value!({ a: { b: [{ c: 1 }, { c: "two" }] } });
+	assert_value(value.query("a.b.0.c").unwrap(), &nix_value!(1));
+	assert_value(value.query("a.b.1.c").unwrap(), &nix_value!("two"));
+	assert_value(value.query("a.b.1").unwrap(), &nix_value!({ c: "two" }));
+}
+
+#[test]
+fn query_of_a_missing_path_is_none() {
+	let value = nix_value!({ a: { b: [{ c: 1 }] } });
+	assert!(value.query("a.x").is_none());
+	assert!(value.query("a.b.1.c").is_none());
+	assert!(value.query("a.b.c").is_none());
+}
+
+#[test]
+fn normalize_integral_floats_only_converts_integral_floats() {
+	let value = nix_value!([3.0, 3.5]).normalize_integral_floats();
+	let Value::List(items) = &value else {
+		panic!("expected a list, got {value:?}");
+	};
+	assert!(
+		matches!(items[..], [Value::Integer(3), Value::Float(n)] if n == 3.5)
+	);
+}
+
+#[test]
+fn normalize_integral_floats_recurses_into_attrsets() {
+	let value = nix_value!({ a: { b: [2.0] } }).normalize_integral_floats();
+	assert!(matches!(value.query("a.b.0"), Some(Value::Integer(2))));
+}
+
+#[test]
+fn sum_and_product_fold_numbers() {
+	assert!(matches!(nix_value!([1, 2, 3]).sum(), Ok(Value::Integer(6))));
+	assert!(matches!(
+		nix_value!([2, 3, 4]).product(),
+		Ok(Value::Integer(24))
+	));
+	assert_value(&nix_value!([1, 0.5]).sum().unwrap(), &nix_value!(1.5));
+}
+
+#[test]
+fn sum_rejects_a_non_numeric_element() {
+	let err = nix_value!([1, "x"]).sum().unwrap_err();
+	assert_eq!(err.to_string(), "cannot apply addition to int and string");
+}
+
+#[test]
+fn all_and_any_fold_booleans() {
+	assert!(nix_value!([true, true]).all().unwrap());
+	assert!(!nix_value!([true, false]).all().unwrap());
+	assert!(nix_value!([true, false]).any().unwrap());
+	assert!(!nix_value!([false, false]).any().unwrap());
+}
+
+#[test]
+fn all_rejects_a_non_boolean_element() {
+	let err = nix_value!([true, 1]).all().unwrap_err();
+	assert_eq!(err.to_string(), "expected bool, got int");
+}
+
+#[test]
+fn map_values_maps_every_scalar_leaf() {
+	let value =
+		nix_value!({ a: 1, b: [2, 3] }).map_values(&|value| match value {
+			Value::Integer(n) => Value::Integer(n * 2),
+			other => other.clone(),
+		});
+	assert_value(&value, &nix_value!({ a: 2, b: [4, 6] }));
+}
+
+#[test]
+fn map_values_leaves_functions_alone() {
+	let value = Parser::default()
+		.parse("{ f = x: x; n = 1; }", "test.nix")
+		.unwrap();
+	let mapped = value.map_values(&|value| {
+		assert!(value.is_scalar(), "called on {value:?}");
+		Value::Null
+	});
+	assert!(matches!(mapped.get("f"), Some(Value::Lambda(..))));
+	assert!(matches!(mapped.get("n"), Some(Value::Null)));
+}
+
+#[test]
+fn approx_eq_compares_numbers_within_epsilon() {
+	let sum = Parser::default().parse("0.1 + 0.2", "test.nix").unwrap();
+	assert!(!sum.approx_eq(&nix_value!(0.3), 0.0));
+	assert!(sum.approx_eq(&nix_value!(0.3), 1e-9));
+	assert!(nix_value!([1.0, 2.0]).approx_eq(&nix_value!([1.05, 2.0]), 0.1));
+	assert!(!nix_value!({ a: 1.0 }).approx_eq(&nix_value!({ a: 1.5 }), 0.1));
+}
+
+#[test]
+fn approx_eq_compares_integers_with_floats_under_the_same_tolerance() {
+	assert!(nix_value!(3).approx_eq(&nix_value!(3.05), 0.1));
+	assert!(!nix_value!(3).approx_eq(&nix_value!(3.5), 0.1));
+	// Two integers are always compared exactly.
+	assert!(!nix_value!(3).approx_eq(&nix_value!(4), 10.0));
+}
+
+#[test]
+fn flatten_flattens_one_level() {
+	assert_value(
+		&nix_value!([[1, 2], [3]]).flatten().unwrap(),
+		&nix_value!([1, 2, 3]),
+	);
+	assert_value(
+		&nix_value!([[[1]], 2]).flatten().unwrap(),
+		&nix_value!([[1], 2]),
+	);
+}
+
+#[test]
+fn flatten_rejects_a_non_list() {
+	let err = nix_value!({ a: [1] }).flatten().unwrap_err();
+	assert_eq!(err.to_string(), "expected list, got set");
+}
+
+#[test]
+fn is_scalar_is_true_only_for_leaves() {
+	let parse = |src| Parser::default().parse(src, "test.nix").unwrap();
+	for src in ["null", "true", "1", "1.5", "\"x\"", "./x"] {
+		assert!(parse(src).is_scalar(), "{src}");
+	}
+	for src in [
+		"[ 1 ]",
+		"{ a = 1; }",
+		"rec { a = 1; }",
+		"let a = 1; in a",
+		"with a; b",
+		"a.b",
+		"a + 1",
+		"if a then 1 else 2",
+		"x: x",
+		"f 1",
+		"\"${a}\"",
+	] {
+		assert!(!parse(src).is_scalar(), "{src}");
+	}
+
+	let mut parser = Parser::default();
+	parser.config.raw_numbers = true;
+	assert!(parser.parse("0xFF", "test.nix").unwrap().is_scalar());
+}
+
+#[test]
+fn nix_value_matches_the_parsed_equivalent() {
+	let parse = |src| Parser::default().parse(src, "test.nix").unwrap();
+	assert_value(
+		&nix_value!({ a: 1, b: [true, null] }),
+		&parse("{ a = 1; b = [ true null ]; }"),
+	);
+	assert_value(
+		&nix_value!({ "a-b": "x", c: { d: 1.5 }, e: [], f: {} }),
+		&parse(r#"{ "a-b" = "x"; c = { d = 1.5; }; e = [ ]; f = { }; }"#),
+	);
+	let n = 3;
+	assert_value(&nix_value!([n, (-1)]), &parse("[ 3 (-1) ]"));
+}
+
+#[test]
+fn retain_attrs_filters_nested_attrsets() {
+	let value = nix_value!({
+		_secret: 1,
+		a: { _token: "x", b: 2 },
+		c: [{ _d: 3, e: 4 }],
+	});
+	let value = value.retain_attrs(|key, _| !key.starts_with('_'));
+	assert_value(&value, &nix_value!({ a: { b: 2 }, c: [{ e: 4 }] }));
+}
+
+#[test]
+fn retain_attrs_passes_through_non_attrsets() {
+	let value = nix_value!("x").retain_attrs(|_, _| false);
+	assert_value(&value, &nix_value!("x"));
+}
+
+#[test]
+fn is_homogeneous_list_names_the_shared_type() {
+	assert_eq!(nix_value!([1, 2, 3]).is_homogeneous_list(), Some("int"));
+	assert_eq!(nix_value!(["a", "b"]).is_homogeneous_list(), Some("string"));
+	assert_eq!(nix_value!([1, "x"]).is_homogeneous_list(), None);
+	assert_eq!(nix_value!([]).is_homogeneous_list(), None);
+	assert_eq!(nix_value!({ a: 1 }).is_homogeneous_list(), None);
+}
+
+#[test]
+fn to_path_buf_resolves_relative_paths_against_the_base() {
+	let base = Path::new("/home/user/config");
+	let parse = |src| Parser::default().parse(src, "test.nix").unwrap();
+
+	let relative = parse("./x");
+	assert_eq!(relative.as_path(), Some("./x"));
+	assert_eq!(
+		relative.to_path_buf(base),
+		Some(PathBuf::from("/home/user/config/x"))
+	);
+	assert_eq!(
+		parse("../x/y").to_path_buf(base),
+		Some(PathBuf::from("/home/user/x/y"))
+	);
+	assert_eq!(
+		parse("/etc/x").to_path_buf(base),
+		Some(PathBuf::from("/etc/x"))
+	);
+}
+
+#[test]
+fn as_path_is_none_for_a_string() {
+	assert_eq!(nix_value!("./x").as_path(), None);
+	assert_eq!(nix_value!("./x").to_path_buf(Path::new("/")), None);
+}
+
+#[test]
+fn split_by_prefix_partitions_flat_keys() {
+	let value = Parser::default()
+		.parse(
+			r#"{ "db.host" = "x"; "db.port" = 5432; "web.port" = 8080; }"#,
+			"test.nix",
+		)
+		.unwrap();
+	let (db, rest) = value.split_by_prefix("db.");
+	assert_value(&db, &nix_value!({ host: "x", port: 5432 }));
+	assert_value(&rest, &nix_value!({ "web.port": 8080 }));
+}
+
+#[test]
+fn split_by_prefix_of_a_non_attrset_is_all_rest() {
+	let (matching, rest) = nix_value!([1]).split_by_prefix("a");
+	assert_value(&matching, &nix_value!({}));
+	assert_value(&rest, &nix_value!([1]));
+}
+
+#[test]
+fn rename_attr_renames_a_top_level_key() {
+	let value = nix_value!({ old: 1 }).rename_attr("old", "new");
+	assert_value(&value, &nix_value!({ new: 1 }));
+}
+
+#[test]
+fn rename_attr_skips_a_taken_name() {
+	let value = nix_value!({ old: 1, new: 2 }).rename_attr("old", "new");
+	assert_value(&value, &nix_value!({ old: 1, new: 2 }));
+	let value = nix_value!({ a: 1 }).rename_attr("old", "new");
+	assert_value(&value, &nix_value!({ a: 1 }));
+}
+
+#[test]
+fn to_env_vars_flattens_nested_attrsets() {
+	let value = nix_value!({
+		db: { host: "x", port: 5432 },
+		"log-level": null,
+		debug: true,
+	});
+	let vars = value.to_env_vars("APP").unwrap();
+	let vars = vars
+		.iter()
+		.map(|(name, value)| (name.as_str(), value.as_str()))
+		.collect::<Vec<_>>();
+	assert_eq!(
+		vars,
+		[
+			("APP_DB_HOST", "x"),
+			("APP_DB_PORT", "5432"),
+			("APP_DEBUG", "true"),
+			("APP_LOG_LEVEL", ""),
+		]
+	);
+}
+
+#[test]
+fn to_env_vars_rejects_keys_that_give_the_same_name() {
+	let err = nix_value!({ "a-b": 1, a_b: 2 })
+		.to_env_vars("APP")
+		.unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"renaming both `a-b` and `a_b` gives `APP_A_B`"
+	);
+	let err = nix_value!({ a: { b: 1 }, a_b: 2 })
+		.to_env_vars("APP")
+		.unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"renaming both `a.b` and `a_b` gives `APP_A_B`"
+	);
+}
+
+#[test]
+fn to_env_vars_rejects_lists() {
+	let err = nix_value!({ db: { hosts: ["a", "b"] } })
+		.to_env_vars("APP")
+		.unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"cannot export `APP_DB_HOSTS`, a list, as an environment variable"
+	);
+}
+
+#[test]
+fn coerce_to_string_accepts_strings_and_paths() {
+	assert_eq!(nix_value!("x").coerce_to_string().unwrap(), "x");
+	let path = Parser::default().parse("./a/b", "test.nix").unwrap();
+	assert_eq!(path.coerce_to_string().unwrap(), "./a/b");
+	let drv = nix_value!({ outPath: "/nix/store/x", name: "x" });
+	assert_eq!(drv.coerce_to_string().unwrap(), "/nix/store/x");
+}
+
+#[test]
+fn coerce_to_string_rejects_integers() {
+	let err = nix_value!(1).coerce_to_string().unwrap_err();
+	assert_eq!(err.to_string(), "cannot coerce int to a string");
+	assert!(nix_value!(true).coerce_to_string().is_err());
+	assert!(nix_value!({ name: "x" }).coerce_to_string().is_err());
+}
+
+#[test]
+fn insert_edits_a_parsed_attrset_in_place() {
+	let src = String::from("{ a = 1; }");
+	let mut value: OwnedValue = Parser::default()
+		.parse(&src, "test.nix")
+		.unwrap()
+		.into_owned();
+	drop(src);
+
+	let old = value.insert("b", nix_value!([true])).unwrap();
+	assert!(old.is_none());
+	let old = value.insert(String::from("a"), nix_value!(2)).unwrap();
+	assert!(matches!(old, Some(Value::Integer(1))));
+	value.as_attrset_mut().unwrap().remove("missing");
+	assert_eq!(value.to_nix(), "{\n  a = 2;\n  b = [ true ];\n}");
+}
+
+#[test]
+fn insert_into_a_non_attrset_is_an_error() {
+	let mut value = nix_value!([1]);
+	assert!(value.as_attrset_mut().is_none());
+	let err = value.insert("a", nix_value!(1)).unwrap_err();
+	assert_eq!(err.to_string(), "expected set, got list");
+}
+
+#[test]
+fn type_histogram_counts_every_value() {
+	let histogram = Parser::default()
+		.parse("{ a = 1; b = [ true null ]; }", "test.nix")
+		.unwrap()
+		.type_histogram();
+	let expected = HashMap::from([
+		("set", 1),
+		("int", 1),
+		("list", 1),
+		("bool", 1),
+		("null", 1),
+	]);
+	assert_eq!(histogram, expected);
+}
+
+#[test]
+fn unwrap_or_replaces_only_null() {
+	let default = nix_value!(8080);
+	assert!(nix_value!(null).is_null());
+	assert_value(nix_value!(null).unwrap_or(&default), &default);
+
+	let port = nix_value!(80);
+	assert!(!port.is_null());
+	assert_value(port.unwrap_or(&default), &port);
+	// A false or empty value isn't null.
+	assert_value(nix_value!(false).unwrap_or(&default), &nix_value!(false));
+}
+
+#[test]
+fn prune_nulls_removes_null_attributes_but_not_elements() {
+	let value = Parser::default()
+		.parse(
+			"{ a = 1; b = null; c = [ 1 null ]; d = { e = null; }; }",
+			"t",
+		)
+		.unwrap()
+		.prune_nulls();
+	assert_value(&value, &nix_value!({ a: 1, c: [1, null], d: {} }));
+}
+
+#[test]
+fn attr_paths_lists_every_leaf() {
+	let value = nix_value!({ a: { b: 1, c: 2 }, d: 3, e: {}, f: [{ g: 4 }] });
+	assert_eq!(
+		value.attr_paths(),
+		[vec!["a", "b"], vec!["a", "c"], vec!["d"], vec!["f"]]
+	);
+	// A value that isn't an attrset is a leaf at the empty path.
+	assert_eq!(nix_value!(1).attr_paths(), [Vec::<String>::new()]);
+}
+
+#[test]
+fn merge_with_resolves_conflicts_with_the_callback() {
+	let base = nix_value!({ a: 1, b: { c: 5, d: "x" }, e: 2 });
+	let other = nix_value!({ a: 3, b: { c: 4 }, f: 6 });
+	let paths = std::cell::RefCell::new(Vec::new());
+	let merged = base.merge_with(&other, |path, lhs, rhs| {
+		paths.borrow_mut().push(path.join("."));
+		match (lhs, rhs) {
+			(Value::Integer(a), Value::Integer(b)) => Value::Integer(*a.max(b)),
+			_ => rhs.clone(),
+		}
+	});
+	assert_value(
+		&merged,
+		&nix_value!({ a: 3, b: { c: 5, d: "x" }, e: 2, f: 6 }),
+	);
+	let mut paths = paths.into_inner();
+	paths.sort();
+	assert_eq!(paths, ["a", "b.c"]);
+}
+
+#[test]
+fn leaves_yields_scalars_in_pre_order() {
+	let value = Parser::default()
+		.parse("{ b = [ 2 3 ]; a = 1; c = { }; }", "test.nix")
+		.unwrap();
+	let leaves = value
+		.leaves()
+		.map(|leaf| match leaf {
+			Value::Integer(n) => *n,
+			other => panic!("unexpected leaf {other:?}"),
+		})
+		.collect::<Vec<_>>();
+	assert_eq!(leaves, [1, 2, 3]);
+	assert_eq!(value.leaf_count(), 3);
+	assert_eq!(nix_value!("x").leaf_count(), 1);
+}
+
+#[test]
+fn to_key_value_lines_writes_one_line_per_attribute() {
+	let lines = Parser::default()
+		.parse(r#"{ b = "x"; a = 1; }"#, "test.nix")
+		.unwrap()
+		.to_key_value_lines('=')
+		.unwrap();
+	assert_eq!(lines, "a=1\nb=x\n");
+}
+
+#[test]
+fn to_key_value_lines_rejects_nested_values() {
+	let err = nix_value!({ a: { b: 1 } })
+		.to_key_value_lines('=')
+		.unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"cannot write `a`, a set, as a key-value line"
+	);
+	assert!(nix_value!({ a: [1] }).to_key_value_lines('=').is_err());
+	assert!(nix_value!([1]).to_key_value_lines('=').is_err());
+}
+
+#[test]
+fn find_duplicate_values_reports_equal_attributes() {
+	let value = Parser::default()
+		.parse("{ a = [ 1 2 ]; b = [ 1 2 ]; c = [ 2 1 ]; }", "test.nix")
+		.unwrap();
+	assert_eq!(
+		value.find_duplicate_values(),
+		[(vec!["a".to_owned()], vec!["b".to_owned()])]
+	);
+}
+
+#[test]
+fn find_duplicate_values_reports_equal_attrsets_once() {
+	let value = nix_value!({ x: { p: 1, q: 2 }, y: { p: 1, q: 2 } });
+	assert_eq!(
+		value.find_duplicate_values(),
+		[(vec!["x".to_owned()], vec!["y".to_owned()])]
+	);
+}
+
+#[test]
+fn apply_defaults_fills_in_only_missing_keys() {
+	let config = nix_value!({ db: { host: "prod" }, debug: true });
+	let defaults = nix_value!({
+		db: { host: "localhost", port: 5432 },
+		debug: false,
+		name: "app",
+	});
+	assert_value(
+		&config.apply_defaults(&defaults),
+		&nix_value!({
+			db: { host: "prod", port: 5432 },
+			debug: true,
+			name: "app",
+		}),
+	);
+}
+
+#[test]
+fn total_attrs_counts_nested_attributes() {
+	let value = nix_value!({ a: { b: 1, c: 2 }, d: 3 });
+	assert_eq!(value.total_attrs(), 4);
+	let value = nix_value!({ a: [{ b: 1 }, { c: { d: 2 } }] });
+	assert_eq!(value.total_attrs(), 4);
+	assert_eq!(nix_value!(1).total_attrs(), 0);
+}
+
+#[test]
+fn to_owned_value_outlives_the_source() {
+	let src = String::from(r#"{ a = "x"; b = [ ./p { c = 1; } ]; }"#);
+	let borrowed = Parser::default().parse(&src, "test.nix").unwrap();
+	let owned = borrowed.to_owned_value();
+	// The borrowed tree is still usable alongside the copy.
+	assert_value(&borrowed, &owned);
+	drop(borrowed);
+	drop(src);
+	assert_eq!(owned.to_nix(), "{\n  a = \"x\";\n  b = [\n    ./p\n    {\n      c = 1;\n    }\n  ];\n}");
+}
+
+fn checked_sum(acc: i64, value: &Value) -> Result<i64, &'static str> {
+	match value {
+		Value::Integer(n) => acc.checked_add(*n).ok_or("overflow"),
+		_ => Err("not an integer"),
+	}
+}
+
+#[test]
+fn try_fold_sums_leaves() {
+	assert_eq!(nix_value!([1, 2, 3]).try_fold(0, checked_sum), Ok(6));
+}
+
+#[test]
+fn try_fold_stops_at_the_first_error() {
+	let value = nix_value!([1, (i64::MAX), "x"]);
+	let mut seen = 0;
+	let result = value.try_fold(0, |acc, value| {
+		seen += 1;
+		checked_sum(acc, value)
+	});
+	assert_eq!(result, Err("overflow"));
+	assert_eq!(seen, 2);
+}
+
+fn env_name(key: &str) -> String {
+	key.chars()
+		.map(|c| {
+			if c.is_ascii_alphanumeric() {
+				c.to_ascii_uppercase()
+			} else {
+				'_'
+			}
+		})
+		.collect()
+}
+
+#[test]
+fn sanitize_keys_renames_keys_recursively() {
+	let value = Parser::default()
+		.parse(r#"{ "a-b" = 1; c = [ { "d.e" = 2; } ]; }"#, "test.nix")
+		.unwrap()
+		.sanitize_keys(env_name)
+		.unwrap();
+	assert_value(&value, &nix_value!({ A_B: 1, C: [{ D_E: 2 }] }));
+}
+
+#[test]
+fn sanitize_keys_rejects_keys_renamed_alike() {
+	let err = nix_value!({ "a-b": 1, "a_b": 2 })
+		.sanitize_keys(env_name)
+		.unwrap_err();
+	assert_eq!(err.to_string(), "renaming both `a-b` and `a_b` gives `A_B`");
+}
+
+#[test]
+fn group_by_groups_attrsets_by_a_key() {
+	let value = Parser::default()
+		.parse(
+			r#"[ { t = "a"; v = 1; } { t = "a"; v = 2; } { t = "b"; v = 3; } ]"#,
+			"test.nix",
+		)
+		.unwrap();
+	assert_value(
+		&value.group_by("t").unwrap(),
+		&nix_value!({
+			a: [{ t: "a", v: 1 }, { t: "a", v: 2 }],
+			b: [{ t: "b", v: 3 }],
+		}),
+	);
+}
+
+#[test]
+fn group_by_rejects_bad_elements() {
+	let err = nix_value!([{ t: "a" }, 1]).group_by("t").unwrap_err();
+	assert_eq!(err.to_string(), "expected set, got int");
+	let err = nix_value!([{ t: "a" }, { v: 1 }])
+		.group_by("t")
+		.unwrap_err();
+	assert_eq!(err.to_string(), "attribute `t` is missing");
+	let err = nix_value!({ t: "a" }).group_by("t").unwrap_err();
+	assert_eq!(err.to_string(), "expected list, got set");
+}
+
+#[test]
+fn to_flat_map_joins_key_paths() {
+	let flat = nix_value!({ a: { b: 1, c: { d: "x" } }, e: null })
+		.to_flat_map('.')
+		.unwrap();
+	assert_eq!(flat.len(), 3);
+	assert_value(&flat["a.b"], &nix_value!(1));
+	assert_value(&flat["a.c.d"], &nix_value!("x"));
+	assert_value(&flat["e"], &nix_value!(null));
+
+	let flat = nix_value!({ a: { b: 1 } }).to_flat_map('/').unwrap();
+	assert_value(&flat["a/b"], &nix_value!(1));
+}
+
+#[test]
+fn to_flat_map_rejects_lists() {
+	let err = nix_value!({ a: { b: [1] } }).to_flat_map('.').unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"cannot flatten `a.b`, a list, into a single key"
+	);
+}
+
+#[test]
+fn to_flat_map_rejects_paths_that_collide() {
+	let value = Parser::default()
+		.parse(r#"{ "a.b" = 1; a = { b = 2; }; }"#, "test.nix")
+		.unwrap();
+	let err = value.to_flat_map('.').unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		r#"`a.b` and `"a.b"` both flatten to the key `a.b`"#
+	);
+	// With another separator, they don't collide.
+	assert_eq!(value.to_flat_map('/').unwrap().len(), 2);
+}
+
+#[test]
+fn ensure_type_passes_a_matching_type_through() {
+	let value = nix_value!([1, 2]);
+	let checked = value.ensure_type("list").unwrap();
+	assert!(std::ptr::eq(checked, &value));
+	assert!(nix_value!({}).ensure_type("set").is_ok());
+}
+
+#[test]
+fn ensure_type_reports_a_mismatch() {
+	let err = nix_value!(1).ensure_type("list").unwrap_err();
+	assert_eq!(err.to_string(), "expected list, got int");
+
+	// The expected type can come from elsewhere, such as a schema, and
+	// needn't outlive the error.
+	let expected = String::from("set");
+	let err = nix_value!(1).ensure_type(&expected).unwrap_err();
+	drop(expected);
+	assert_eq!(err.to_string(), "expected set, got int");
+}
+
+#[test]
+fn select_first_returns_the_first_present_key() {
+	let value = nix_value!({ hostname: "new", host: "old" });
+	let found = value.select_first(&["host_name", "hostname", "host"]);
+	assert_value(found.unwrap(), &nix_value!("new"));
+	assert!(value.select_first(&["a", "b"]).is_none());
+	assert!(nix_value!([1]).select_first(&["a"]).is_none());
+}
+
+#[test]
+fn with_attr_builds_an_attrset() {
+	let value = nix_value!({}).with_attr("a", 1).with_attr("b", 2);
+	assert_value(&value, &nix_value!({ a: 1, b: 2 }));
+	let value = value.with_attr("a", "x");
+	assert_value(&value, &nix_value!({ a: "x", b: 2 }));
+}
+
+#[test]
+#[should_panic(expected = "with_attr called on a list, not a set")]
+fn with_attr_panics_on_a_non_attrset() {
+	nix_value!([]).with_attr("a", 1);
+}
+
+#[test]
+fn zip_pairs_up_list_elements() {
+	let zipped = nix_value!([1, 2]).zip(&nix_value!(["a", "b"])).unwrap();
+	assert_value(
+		&zipped,
+		&nix_value!([{ fst: 1, snd: "a" }, { fst: 2, snd: "b" }]),
+	);
+}
+
+#[test]
+fn zip_rejects_mismatched_lengths_and_non_lists() {
+	let err = nix_value!([1, 2]).zip(&nix_value!(["a"])).unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"cannot zip lists of different lengths, 2 and 1"
+	);
+	let err = nix_value!([1]).zip(&nix_value!({})).unwrap_err();
+	assert_eq!(err.to_string(), "expected list, got set");
+}
+
+#[test]
+fn expect_attrset_and_expect_list_return_the_contents() {
+	let value = nix_value!({ hosts: ["a", "b"] });
+	let map = value.expect_attrset().unwrap();
+	let hosts = map["hosts"].expect_list().unwrap();
+	assert_eq!(hosts.len(), 2);
+	assert_value(&hosts[0], &nix_value!("a"));
+}
+
+#[test]
+fn expect_attrset_and_expect_list_report_mismatches() {
+	let err = nix_value!([1]).expect_attrset().unwrap_err();
+	assert_eq!(err.to_string(), "expected set, got list");
+	let err = nix_value!({}).expect_list().unwrap_err();
+	assert_eq!(err.to_string(), "expected list, got set");
+}
+
+#[test]
+fn dedup_list_keeps_first_occurrences() {
+	let value = Parser::default().parse("[ 1 2 2 3 1 ]", "t").unwrap();
+	assert_value(&value.dedup_list().unwrap(), &nix_value!([1, 2, 3]));
+	let value = nix_value!([{ a: 1 }, 1.0, { a: 1 }, 1]);
+	assert_value(&value.dedup_list().unwrap(), &nix_value!([{ a: 1 }, 1.0]));
+}
+
+#[test]
+fn dedup_list_rejects_a_non_list() {
+	let err = nix_value!({}).dedup_list().unwrap_err();
+	assert_eq!(err.to_string(), "expected list, got set");
+}
+
+#[test]
+fn to_nix_file_ends_in_one_newline_and_parses_back() {
+	let value = Parser::default()
+		.parse(
+			r#"{ a = [ 1 2.5 "x" ]; b = { c = null; d = ./p; }; e = x: x; }"#,
+			"test.nix",
+		)
+		.unwrap();
+	let file = value.to_nix_file();
+	assert!(file.ends_with("}\n") && !file.ends_with("\n\n"), "{file:?}");
+
+	let reparsed = Parser::default().parse(&file, "written.nix").unwrap();
+	assert_value(&reparsed, &value);
+	assert_eq!(reparsed.to_nix_file(), file);
+}
+
+#[test]
+fn to_nix_file_of_a_scalar_is_one_line() {
+	assert_eq!(nix_value!(42).to_nix_file(), "42\n");
+}